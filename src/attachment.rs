@@ -0,0 +1,296 @@
+/*
+ * attachment.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Storage for files attached to pages.
+//!
+//! Attachments are kept on disk under the upload directory, in the layout
+//! `{wiki-slug}/{page-slug}/{filename}`, and served at `/local--files/{page}/{filename}`.
+//!
+//! These methods do blocking filesystem work, so handlers should call them through `web::block`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Longest allowed attachment filename, in bytes.
+pub const MAX_FILENAME_LENGTH: usize = 255;
+
+/// Counter to give each upload's temporary file a different name.
+static UPLOAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Information about a file attached to a page.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Determines if a name can be used as a single path component.
+///
+/// This prevents both wiki and page slugs, and filenames, from escaping
+/// the upload directory.
+pub fn is_valid_filename(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_FILENAME_LENGTH
+        && name != "."
+        && name != ".."
+        && !name.starts_with('.')
+        && !name.contains(|c: char| c == '/' || c == '\\' || c.is_control())
+}
+
+fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid attachment path component: {:?}", name),
+    )
+}
+
+/// The files attached to pages, shared between workers.
+#[derive(Debug, Clone)]
+pub struct Attachments {
+    root: Arc<PathBuf>,
+    max_size: usize,
+}
+
+impl Attachments {
+    /// Creates a new attachment store in `root`, where no file may be larger than `max_size` bytes.
+    pub fn new(root: PathBuf, max_size: usize) -> Self {
+        Attachments {
+            root: Arc::new(root),
+            max_size,
+        }
+    }
+
+    #[inline]
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn page_dir(&self, wiki_slug: &str, page_slug: &str) -> io::Result<PathBuf> {
+        for name in &[wiki_slug, page_slug] {
+            if !is_valid_filename(name) {
+                return Err(invalid_name(name));
+            }
+        }
+
+        let mut path = PathBuf::from(&*self.root);
+        path.push(wiki_slug);
+        path.push(page_slug);
+        Ok(path)
+    }
+
+    fn file_path(&self, wiki_slug: &str, page_slug: &str, filename: &str) -> io::Result<PathBuf> {
+        if !is_valid_filename(filename) {
+            return Err(invalid_name(filename));
+        }
+
+        let mut path = self.page_dir(wiki_slug, page_slug)?;
+        path.push(filename);
+        Ok(path)
+    }
+
+    /// Gets the path to an attached file, if it exists.
+    pub fn get(&self, wiki_slug: &str, page_slug: &str, filename: &str) -> Option<PathBuf> {
+        let path = self.file_path(wiki_slug, page_slug, filename).ok()?;
+
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Lists all the files attached to a page, sorted by name.
+    pub fn list(&self, wiki_slug: &str, page_slug: &str) -> io::Result<Vec<Attachment>> {
+        let dir = self.page_dir(wiki_slug, page_slug)?;
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+
+        let mut attachments = Vec::new();
+
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            if !metadata.is_file() || !is_valid_filename(&name) {
+                continue;
+            }
+
+            attachments.push(Attachment {
+                name,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+
+        attachments.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(attachments)
+    }
+
+    /// Starts uploading a file to a page, see `Upload`.
+    pub fn upload(&self, wiki_slug: &str, page_slug: &str, filename: &str) -> io::Result<Upload> {
+        let path = self.file_path(wiki_slug, page_slug, filename)?;
+        let temp_path = path.with_file_name(format!(
+            ".upload-{}-{}",
+            process::id(),
+            UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+
+        fs::create_dir_all(self.page_dir(wiki_slug, page_slug)?)?;
+        let file = fs::File::create(&temp_path)?;
+
+        Ok(Upload {
+            file: Some(file),
+            temp_path,
+            path,
+        })
+    }
+
+    /// Renames a file attached to a page.
+    ///
+    /// Returns `false` if there is no such file, and an `AlreadyExists` error
+    /// if the new name is taken.
+    pub fn rename(
+        &self,
+        wiki_slug: &str,
+        page_slug: &str,
+        filename: &str,
+        new_filename: &str,
+    ) -> io::Result<bool> {
+        let path = self.file_path(wiki_slug, page_slug, filename)?;
+        let new_path = self.file_path(wiki_slug, page_slug, new_filename)?;
+
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        if new_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Attachment '{}' already exists", new_filename),
+            ));
+        }
+
+        fs::rename(&path, &new_path)?;
+        Ok(true)
+    }
+
+    /// Removes a file attached to a page.
+    /// Returns `false` if there was no such file.
+    pub fn remove(&self, wiki_slug: &str, page_slug: &str, filename: &str) -> io::Result<bool> {
+        let path = self.file_path(wiki_slug, page_slug, filename)?;
+
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Moves all of a page's files when it is renamed.
+    pub fn move_page(
+        &self,
+        wiki_slug: &str,
+        page_slug: &str,
+        new_page_slug: &str,
+    ) -> io::Result<()> {
+        let dir = self.page_dir(wiki_slug, page_slug)?;
+        let new_dir = self.page_dir(wiki_slug, new_page_slug)?;
+
+        if !dir.is_dir() {
+            return Ok(());
+        }
+
+        fs::rename(&dir, &new_dir)
+    }
+
+    /// Removes all of a page's files when it is permanently deleted.
+    pub fn remove_page(&self, wiki_slug: &str, page_slug: &str) -> io::Result<()> {
+        let dir = self.page_dir(wiki_slug, page_slug)?;
+
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// A file being uploaded to a page, written as its data arrives.
+///
+/// The data goes to a temporary file, which replaces any existing file with
+/// the same name once the upload is finished. Its name is unique, so concurrent
+/// uploads of the same file do not write over each other, and it is removed
+/// if the upload is dropped before then, so a failed upload leaves nothing behind.
+#[derive(Debug)]
+pub struct Upload {
+    file: Option<fs::File>,
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl Upload {
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.write_all(data),
+            None => unreachable!("Upload already finished"),
+        }
+    }
+
+    /// Saves the uploaded file in place of any existing one.
+    pub fn finish(mut self) -> io::Result<()> {
+        let result = match self.file.take() {
+            Some(file) => {
+                let synced = file.sync_all();
+                drop(file);
+
+                synced.and_then(|_| fs::rename(&self.temp_path, &self.path))
+            }
+            None => unreachable!("Upload already finished"),
+        };
+
+        if result.is_err() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+
+        result
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // Finished uploads have already been moved into place or cleaned up
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * cache.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! In-process cache of rendered page HTML.
+//!
+//! Entries are keyed by wiki, page slug, and revision ID, and the cache
+//! is bounded by the approximate memory used by its entries. When it is
+//! full, the least recently used entries are evicted.
+//!
+//! Each entry also records the pages it included and the pages above it in the
+//! hierarchy, so that changing a page also invalidates every render which included
+//! it or shows it in its breadcrumbs.
+
+use deepwell_core::types::{RevisionId, WikiId};
+use lru::LruCache;
+use std::fmt::{self, Debug};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+struct CacheKey {
+    wiki_id: WikiId,
+    slug: String,
+    revision_id: RevisionId,
+}
+
+/// A page which is above another in the hierarchy.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Ancestor {
+    pub slug: String,
+    pub title: String,
+}
+
+/// The output of rendering a page with ftml.
+#[derive(Debug, Clone)]
+pub struct CachedRender {
+    pub html: String,
+    pub style: String,
+    pub includes: Vec<String>,
+    pub missing_includes: Vec<String>,
+    pub ancestors: Vec<Ancestor>,
+}
+
+impl CachedRender {
+    fn size(&self, key: &CacheKey) -> usize {
+        mem::size_of::<CacheKey>()
+            + mem::size_of::<Self>()
+            + key.slug.len()
+            + self.html.len()
+            + self.style.len()
+            + self
+                .includes
+                .iter()
+                .chain(&self.missing_includes)
+                .map(|slug| mem::size_of::<String>() + slug.len())
+                .sum::<usize>()
+            + self
+                .ancestors
+                .iter()
+                .map(|ancestor| {
+                    mem::size_of::<Ancestor>() + ancestor.slug.len() + ancestor.title.len()
+                })
+                .sum::<usize>()
+    }
+
+    /// Determines if this render needs to change when the given page does.
+    fn depends_on(&self, slug: &str) -> bool {
+        self.includes.iter().any(|include| include == slug)
+            || self.ancestors.iter().any(|ancestor| ancestor.slug == slug)
+    }
+}
+
+struct CacheInner {
+    entries: LruCache<CacheKey, Arc<CachedRender>>,
+    size: usize,
+    capacity: usize,
+}
+
+impl CacheInner {
+    fn remove_where<F>(&mut self, f: F)
+    where
+        F: Fn(&CacheKey, &CachedRender) -> bool,
+    {
+        let keys: Vec<CacheKey> = self
+            .entries
+            .iter()
+            .filter(|(key, render)| f(key, render))
+            .map(|(key, _)| key)
+            .cloned()
+            .collect();
+
+        for key in keys {
+            if let Some(render) = self.entries.pop(&key) {
+                self.size -= render.size(&key);
+            }
+        }
+    }
+}
+
+/// Memory-bounded LRU cache of rendered pages, shared between workers.
+#[derive(Clone)]
+pub struct RenderCache {
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+impl RenderCache {
+    /// Creates a new cache which holds at most `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        let inner = CacheInner {
+            entries: LruCache::unbounded(),
+            size: 0,
+            capacity,
+        };
+
+        RenderCache {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    pub fn get(
+        &self,
+        wiki_id: WikiId,
+        slug: &str,
+        revision_id: RevisionId,
+    ) -> Option<Arc<CachedRender>> {
+        let key = CacheKey {
+            wiki_id,
+            slug: String::from(slug),
+            revision_id,
+        };
+
+        let mut inner = self.inner.lock().expect("Render cache lock poisoned");
+        let render = inner.entries.get(&key).cloned();
+
+        trace!(
+            "Render cache {} for '{}' revision {} in wiki ID {}",
+            if render.is_some() { "hit" } else { "miss" },
+            slug,
+            revision_id,
+            wiki_id,
+        );
+
+        render
+    }
+
+    pub fn insert(
+        &self,
+        wiki_id: WikiId,
+        slug: &str,
+        revision_id: RevisionId,
+        render: CachedRender,
+    ) -> Arc<CachedRender> {
+        let key = CacheKey {
+            wiki_id,
+            slug: String::from(slug),
+            revision_id,
+        };
+
+        let size = render.size(&key);
+        let render = Arc::new(render);
+        let mut inner = self.inner.lock().expect("Render cache lock poisoned");
+
+        if size > inner.capacity {
+            debug!(
+                "Rendered page '{}' too large to cache ({} bytes)",
+                slug, size
+            );
+
+            return render;
+        }
+
+        if let Some(old) = inner.entries.put(key.clone(), Arc::clone(&render)) {
+            inner.size -= old.size(&key);
+        }
+
+        inner.size += size;
+
+        // Evict until we're under the size limit
+        while inner.size > inner.capacity {
+            match inner.entries.pop_lru() {
+                Some((key, render)) => inner.size -= render.size(&key),
+                None => break,
+            }
+        }
+
+        render
+    }
+
+    /// Removes all cached renders of a page, for instance after it is edited or deleted.
+    /// Renders of other pages which include it or are below it are also removed.
+    pub fn invalidate_page(&self, wiki_id: WikiId, slug: &str) {
+        debug!(
+            "Invalidating cached renders for '{}' in wiki ID {}",
+            slug, wiki_id
+        );
+
+        let mut inner = self.inner.lock().expect("Render cache lock poisoned");
+        inner.remove_where(|key, render| {
+            key.wiki_id == wiki_id && (key.slug == slug || render.depends_on(slug))
+        });
+    }
+}
+
+impl Debug for RenderCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RenderCache")
+            .field("inner", &"Mutex<lru::LruCache { .. }>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(html: &str, includes: &[&str]) -> CachedRender {
+        CachedRender {
+            html: String::from(html),
+            style: String::new(),
+            includes: includes.iter().map(|slug| String::from(*slug)).collect(),
+            missing_includes: Vec::new(),
+            ancestors: Vec::new(),
+        }
+    }
+
+    fn wiki(id: i64) -> WikiId {
+        WikiId::from_raw(id)
+    }
+
+    fn key(wiki_id: i64, slug: &str, revision_id: i64) -> CacheKey {
+        CacheKey {
+            wiki_id: wiki(wiki_id),
+            slug: String::from(slug),
+            revision_id: RevisionId::from_raw(revision_id),
+        }
+    }
+
+    fn size(cache: &RenderCache) -> usize {
+        cache.inner.lock().unwrap().size
+    }
+
+    #[test]
+    fn get_and_insert() {
+        let cache = RenderCache::new(4096);
+        let revision = RevisionId::from_raw(1);
+
+        assert!(cache.get(wiki(1), "page", revision).is_none());
+        cache.insert(wiki(1), "page", revision, render("<p>A</p>", &[]));
+
+        let cached = cache.get(wiki(1), "page", revision).unwrap();
+        assert_eq!(cached.html, "<p>A</p>");
+        assert!(cache.get(wiki(2), "page", revision).is_none());
+        assert!(cache
+            .get(wiki(1), "page", RevisionId::from_raw(2))
+            .is_none());
+    }
+
+    #[test]
+    fn size_accounting() {
+        let cache = RenderCache::new(4096);
+        let first = render("<p>First</p>", &["component:box"]);
+        let second = render("<p>Second version</p>", &[]);
+        let first_size = first.size(&key(1, "page", 1));
+        let second_size = second.size(&key(1, "page", 1));
+
+        cache.insert(wiki(1), "page", RevisionId::from_raw(1), first);
+        assert_eq!(size(&cache), first_size);
+
+        // Replacing an entry only counts the new one
+        cache.insert(wiki(1), "page", RevisionId::from_raw(1), second);
+        assert_eq!(size(&cache), second_size);
+
+        cache.invalidate_page(wiki(1), "page");
+        assert_eq!(size(&cache), 0);
+    }
+
+    #[test]
+    fn eviction() {
+        let html = "x".repeat(100);
+        let entry_size = render(&html, &[]).size(&key(1, "a", 1));
+        let cache = RenderCache::new(entry_size * 2);
+        let revision = RevisionId::from_raw(1);
+
+        cache.insert(wiki(1), "a", revision, render(&html, &[]));
+        cache.insert(wiki(1), "b", revision, render(&html, &[]));
+
+        // Use "a", so that "b" is the least recently used
+        assert!(cache.get(wiki(1), "a", revision).is_some());
+
+        cache.insert(wiki(1), "c", revision, render(&html, &[]));
+        assert!(cache.get(wiki(1), "a", revision).is_some());
+        assert!(cache.get(wiki(1), "b", revision).is_none());
+        assert!(cache.get(wiki(1), "c", revision).is_some());
+        assert_eq!(size(&cache), entry_size * 2);
+    }
+
+    #[test]
+    fn too_large() {
+        let cache = RenderCache::new(64);
+        let revision = RevisionId::from_raw(1);
+        let output = cache.insert(wiki(1), "page", revision, render(&"x".repeat(100), &[]));
+
+        assert_eq!(output.html.len(), 100);
+        assert!(cache.get(wiki(1), "page", revision).is_none());
+        assert_eq!(size(&cache), 0);
+    }
+
+    #[test]
+    fn invalidate_includes() {
+        let cache = RenderCache::new(4096);
+        let revision = RevisionId::from_raw(1);
+
+        cache.insert(wiki(1), "page", revision, render("", &["component:box"]));
+        cache.insert(wiki(1), "other", revision, render("", &[]));
+        cache.insert(wiki(2), "page", revision, render("", &["component:box"]));
+        cache.invalidate_page(wiki(1), "component:box");
+
+        assert!(cache.get(wiki(1), "page", revision).is_none());
+        assert!(cache.get(wiki(1), "other", revision).is_some());
+        assert!(cache.get(wiki(2), "page", revision).is_some());
+    }
+
+    #[test]
+    fn invalidate_ancestors() {
+        let cache = RenderCache::new(4096);
+        let revision = RevisionId::from_raw(1);
+        let mut child = render("", &[]);
+        child.ancestors.push(Ancestor {
+            slug: String::from("parent"),
+            title: String::from("Parent"),
+        });
+
+        cache.insert(wiki(1), "child", revision, child);
+        cache.insert(wiki(1), "other", revision, render("", &[]));
+        cache.invalidate_page(wiki(1), "parent");
+
+        assert!(cache.get(wiki(1), "child", revision).is_none());
+        assert!(cache.get(wiki(1), "other", revision).is_some());
+    }
+}
@@ -20,8 +20,10 @@
 
 use crate::StdResult;
 use actix_web::cookie::SameSite;
+use deepwell_core::roles::Role;
 use dns_lookup::lookup_host;
 use log::LevelFilter;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::{self, Read};
@@ -32,6 +34,17 @@ use structopt::StructOpt;
 
 const DEFAULT_KEEP_ALIVE: usize = 20;
 const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+const DEFAULT_PREVIEW_RATELIMIT_REQUESTS: usize = 20;
+const DEFAULT_PREVIEW_RATELIMIT_INTERVAL: u64 = 60;
+const DEFAULT_MAIN_PAGE: &str = "start";
+const DEFAULT_SCHEME: &str = "https";
+const DEFAULT_RENDER_CACHE_SIZE: usize = 64;
+const DEFAULT_EDIT_LOCK_DURATION: u64 = 900;
+const DEFAULT_MAX_UPLOAD_SIZE: usize = 10;
+const DEFAULT_TEMPLATE_DIR: &str = "misc/templates";
+const DEFAULT_UPLOAD_DIR: &str = "uploads";
+const DEFAULT_READ_ROLES: [(&str, Role); 2] =
+    [("admin", Role::Admin), ("deleted", Role::Moderator)];
 
 // Structopt argument parsing
 
@@ -66,10 +79,16 @@ pub struct Config {
     pub log_level: LevelFilter,
     pub ratelimit_interval: Duration,
     pub ratelimit_requests: usize,
+    pub preview_ratelimit_interval: Duration,
+    pub preview_ratelimit_requests: usize,
     pub cookie_secure: bool,
     pub cookie_max_age: i64,
     pub cookie_same_site: SameSite,
     pub cookie_key: Box<[u8]>,
+    pub render_cache_size: usize,
+    pub edit_lock_duration: Duration,
+    pub upload_dir: PathBuf,
+    pub max_upload_size: usize,
     // Remote servers
     pub deepwell_address: SocketAddr,
     pub deepwell_timeout: Duration,
@@ -110,7 +129,52 @@ impl Config {
 
 #[derive(Debug, Clone)]
 pub struct RuntimeSettings {
+    pub hostname: String,
+    pub scheme: String,
     pub static_dir: PathBuf,
+    pub template_dir: PathBuf,
+    pub main_page: String,
+    pub site_main_pages: HashMap<String, String>,
+    pub site_names: HashMap<String, String>,
+    pub read_roles: HashMap<String, Role>,
+}
+
+impl RuntimeSettings {
+    /// Gets the slug of the page served at `/` for the given wiki.
+    pub fn main_page(&self, wiki_slug: Option<&str>) -> &str {
+        wiki_slug
+            .and_then(|slug| self.site_main_pages.get(slug))
+            .unwrap_or(&self.main_page)
+    }
+
+    /// Gets the display name of the given wiki, falling back to its slug.
+    pub fn site_name<'a>(&'a self, wiki_slug: Option<&'a str>) -> &'a str {
+        match wiki_slug {
+            Some(slug) => self.site_names.get(slug).map_or(slug, |name| name.as_str()),
+            None => &self.hostname,
+        }
+    }
+
+    /// Gets the canonical hostname for the given wiki.
+    ///
+    /// Wikis are subdomains of the configured hostname, for instance
+    /// `scp-wiki.example.com`, unless the hostname already names the wiki.
+    pub fn canonical_host(&self, wiki_slug: Option<&str>) -> String {
+        match wiki_slug {
+            Some(slug) if self.hostname.split('.').next() != Some(slug) => {
+                format!("{}.{}", slug, self.hostname)
+            }
+            _ => self.hostname.clone(),
+        }
+    }
+
+    /// Gets the base URL of the given wiki, such as `https://scp-wiki.example.com`.
+    ///
+    /// This uses the configured scheme and hostname rather than the request's,
+    /// which may be wrong behind a proxy, or set to anything by the client.
+    pub fn site_url(&self, wiki_slug: Option<&str>) -> String {
+        format!("{}://{}", self.scheme, self.canonical_host(wiki_slug))
+    }
 }
 
 #[serde(rename_all = "kebab-case")]
@@ -119,12 +183,15 @@ struct App {
     log_level: Option<String>,
     ratelimit_requests: usize,
     ratelimit_interval: u64,
+    preview_ratelimit_requests: Option<usize>,
+    preview_ratelimit_interval: Option<u64>,
 }
 
 #[serde(rename_all = "kebab-case")]
 #[derive(Deserialize, Debug)]
 struct Network {
     hostname: String,
+    scheme: Option<String>,
     use_ipv6: bool,
     port: Option<u16>,
     keep_alive: Option<usize>,
@@ -143,6 +210,27 @@ struct Security {
 #[derive(Deserialize, Debug)]
 struct Files {
     static_dir: PathBuf,
+    template_dir: Option<PathBuf>,
+    upload_dir: Option<PathBuf>,
+    max_upload_size: Option<usize>,
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Deserialize, Debug, Default)]
+struct Cache {
+    render_cache_size: Option<usize>,
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Deserialize, Debug, Default)]
+struct Pages {
+    main_page: Option<String>,
+    edit_lock_duration: Option<u64>,
+    #[serde(default)]
+    site_main_pages: HashMap<String, String>,
+    #[serde(default)]
+    site_names: HashMap<String, String>,
+    read_roles: Option<HashMap<String, String>>,
 }
 
 #[serde(rename_all = "kebab-case")]
@@ -200,6 +288,10 @@ struct ConfigFile {
     network: Network,
     security: Security,
     files: Files,
+    #[serde(default)]
+    cache: Cache,
+    #[serde(default)]
+    pages: Pages,
     deepwell: Deepwell,
     ftml: Ftml,
 }
@@ -246,6 +338,15 @@ impl ConfigFile {
         panic!("No log level for '{}'", log_level);
     }
 
+    #[cold]
+    fn parse_scheme(scheme: Option<String>) -> String {
+        match scheme {
+            None => String::from(DEFAULT_SCHEME),
+            Some(scheme) if scheme == "http" || scheme == "https" => scheme,
+            Some(scheme) => panic!("No URL scheme '{}', must be http or https", scheme),
+        }
+    }
+
     #[cold]
     fn parse_same_site(same_site: &str) -> SameSite {
         const POLICIES: [(&str, SameSite); 6] = [
@@ -266,6 +367,48 @@ impl ConfigFile {
         panic!("No same-site cookie policy for '{}'", same_site);
     }
 
+    #[cold]
+    fn parse_read_roles(read_roles: Option<HashMap<String, String>>) -> HashMap<String, Role> {
+        const ROLES: [(&str, Role); 4] = [
+            ("guest", Role::Guest),
+            ("member", Role::Member),
+            ("moderator", Role::Moderator),
+            ("admin", Role::Admin),
+        ];
+
+        let parse_role = |name: &str| {
+            for (text, role) in &ROLES {
+                if name.eq_ignore_ascii_case(text) {
+                    return *role;
+                }
+            }
+
+            panic!("No role named '{}'", name);
+        };
+
+        let mut roles: HashMap<_, _> = match read_roles {
+            Some(read_roles) => read_roles
+                .iter()
+                .map(|(category, role)| (category.to_ascii_lowercase(), parse_role(role)))
+                .collect(),
+            None => DEFAULT_READ_ROLES
+                .iter()
+                .map(|(category, role)| (String::from(*category), *role))
+                .collect(),
+        };
+
+        // Deleted pages are always hidden from the public
+        let deleted = roles
+            .entry(String::from("deleted"))
+            .or_insert(Role::Moderator);
+
+        if *deleted < Role::Moderator {
+            *deleted = Role::Moderator;
+        }
+
+        roles
+    }
+
     #[cold]
     fn read_cookie_key(path: &Path) -> Box<[u8]> {
         let mut file = File::open(path).expect("Unable to open cookie key file");
@@ -292,6 +435,8 @@ impl Into<Config> for ConfigFile {
             network,
             security,
             files,
+            cache,
+            pages,
             deepwell,
             ftml,
         } = self;
@@ -300,10 +445,13 @@ impl Into<Config> for ConfigFile {
             log_level,
             ratelimit_requests,
             ratelimit_interval,
+            preview_ratelimit_requests,
+            preview_ratelimit_interval,
         } = app;
 
         let Network {
             hostname,
+            scheme,
             use_ipv6,
             port,
             keep_alive,
@@ -316,7 +464,22 @@ impl Into<Config> for ConfigFile {
             cookie_key_path,
         } = security;
 
-        let Files { static_dir } = files;
+        let Files {
+            static_dir,
+            template_dir,
+            upload_dir,
+            max_upload_size,
+        } = files;
+
+        let Cache { render_cache_size } = cache;
+
+        let Pages {
+            main_page,
+            edit_lock_duration,
+            site_main_pages,
+            site_names,
+            read_roles,
+        } = pages;
 
         let (deepwell_address, deepwell_timeout, deepwell_pool_size) = deepwell
             .try_into()
@@ -334,9 +497,32 @@ impl Into<Config> for ConfigFile {
 
         let http_address = SocketAddr::new(ip_address, port.unwrap_or(80));
         let keep_alive = keep_alive.unwrap_or(DEFAULT_KEEP_ALIVE);
+        let preview_ratelimit_requests =
+            preview_ratelimit_requests.unwrap_or(DEFAULT_PREVIEW_RATELIMIT_REQUESTS);
+        let preview_ratelimit_interval =
+            preview_ratelimit_interval.unwrap_or(DEFAULT_PREVIEW_RATELIMIT_INTERVAL);
         let log_level = log_level.as_ref().map(|s| s.as_ref());
-
-        let runtime = RuntimeSettings { static_dir };
+        let render_cache_size =
+            render_cache_size.unwrap_or(DEFAULT_RENDER_CACHE_SIZE) * 1024 * 1024;
+
+        let max_upload_size = max_upload_size.unwrap_or(DEFAULT_MAX_UPLOAD_SIZE) * 1024 * 1024;
+        let edit_lock_duration = edit_lock_duration.unwrap_or(DEFAULT_EDIT_LOCK_DURATION);
+        let template_dir = template_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_TEMPLATE_DIR));
+        let upload_dir = upload_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_UPLOAD_DIR));
+        let main_page = main_page
+            .filter(|page| !page.is_empty())
+            .unwrap_or_else(|| String::from(DEFAULT_MAIN_PAGE));
+
+        let runtime = RuntimeSettings {
+            hostname: hostname.clone(),
+            scheme: Self::parse_scheme(scheme),
+            static_dir,
+            template_dir,
+            main_page,
+            site_main_pages,
+            site_names,
+            read_roles: Self::parse_read_roles(read_roles),
+        };
 
         Config {
             hostname,
@@ -345,10 +531,16 @@ impl Into<Config> for ConfigFile {
             log_level: Self::parse_log_level(log_level),
             ratelimit_requests,
             ratelimit_interval: Duration::from_secs(ratelimit_interval),
+            preview_ratelimit_requests,
+            preview_ratelimit_interval: Duration::from_secs(preview_ratelimit_interval),
             cookie_secure,
             cookie_max_age,
             cookie_same_site: Self::parse_same_site(&cookie_same_site),
             cookie_key: Self::read_cookie_key(&cookie_key_path),
+            render_cache_size,
+            edit_lock_duration: Duration::from_secs(edit_lock_duration),
+            upload_dir,
+            max_upload_size,
             deepwell_address,
             deepwell_timeout,
             deepwell_pool_size,
@@ -0,0 +1,395 @@
+/*
+ * diff.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Line and word-level differences between two texts.
+//!
+//! This uses the longest common subsequence of the tokens in each text.
+//! Common prefixes and suffixes are stripped first, and if what remains
+//! is still too large to compare, it is reported as one replacement.
+
+use crate::utils::escape_html;
+use std::mem;
+
+/// Largest number of token comparisons to make before giving up.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// What granularity to compare texts at.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffMode {
+    Line,
+    Word,
+}
+
+impl Default for DiffMode {
+    #[inline]
+    fn default() -> Self {
+        DiffMode::Line
+    }
+}
+
+/// One part of a difference between two texts.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "text", rename_all = "kebab-case")]
+pub enum DiffChunk {
+    Equal(String),
+    Insert(String),
+    Delete(String),
+}
+
+impl DiffChunk {
+    fn text_mut(&mut self) -> &mut String {
+        match self {
+            DiffChunk::Equal(text) | DiffChunk::Insert(text) | DiffChunk::Delete(text) => text,
+        }
+    }
+
+    #[inline]
+    fn same_kind(&self, other: &Self) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+    }
+}
+
+/// Compares two texts, returning the chunks needed to go from `old` to `new`.
+pub fn diff(old: &str, new: &str, mode: DiffMode) -> Vec<DiffChunk> {
+    let (old, new) = match mode {
+        DiffMode::Line => (split_lines(old), split_lines(new)),
+        DiffMode::Word => (split_words(old), split_words(new)),
+    };
+
+    diff_tokens(&old, &new)
+}
+
+/// Formats diff chunks as an HTML fragment using `<ins>` and `<del>`.
+pub fn diff_html(chunks: &[DiffChunk]) -> String {
+    let mut html = String::from("<pre class=\"diff\">");
+
+    for chunk in chunks {
+        match chunk {
+            DiffChunk::Equal(text) => html.push_str(&escape_html(text)),
+            DiffChunk::Insert(text) => {
+                html.push_str("<ins class=\"diff-insert\">");
+                html.push_str(&escape_html(text));
+                html.push_str("</ins>");
+            }
+            DiffChunk::Delete(text) => {
+                html.push_str("<del class=\"diff-delete\">");
+                html.push_str(&escape_html(text));
+                html.push_str("</del>");
+            }
+        }
+    }
+
+    html.push_str("</pre>");
+    html
+}
+
+/// Splits text into lines, keeping the line endings.
+fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+
+    for (index, _) in text.match_indices('\n') {
+        lines.push(&text[start..=index]);
+        start = index + 1;
+    }
+
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+
+    lines
+}
+
+/// Splits text into alternating runs of whitespace and non-whitespace.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+
+    for (index, ch) in text.char_indices() {
+        let is_space = ch.is_whitespace();
+
+        if in_space != Some(is_space) {
+            if index > start {
+                tokens.push(&text[start..index]);
+            }
+
+            start = index;
+            in_space = Some(is_space);
+        }
+    }
+
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+
+    tokens
+}
+
+fn diff_tokens(old: &[&str], new: &[&str]) -> Vec<DiffChunk> {
+    let mut chunks = Vec::new();
+
+    // Strip common prefix and suffix
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    for token in &old[..prefix] {
+        push_chunk(&mut chunks, DiffChunk::Equal(String::from(*token)));
+    }
+
+    if old_middle.len().saturating_mul(new_middle.len()) > MAX_DIFF_CELLS {
+        debug!(
+            "Diff too large ({} x {} tokens), treating as replacement",
+            old_middle.len(),
+            new_middle.len(),
+        );
+
+        push_chunk(&mut chunks, DiffChunk::Delete(old_middle.concat()));
+        push_chunk(&mut chunks, DiffChunk::Insert(new_middle.concat()));
+    } else {
+        diff_lcs(old_middle, new_middle, &mut chunks);
+    }
+
+    for token in &old[old.len() - suffix..] {
+        push_chunk(&mut chunks, DiffChunk::Equal(String::from(*token)));
+    }
+
+    chunks.retain(|chunk| match chunk {
+        DiffChunk::Equal(text) | DiffChunk::Insert(text) | DiffChunk::Delete(text) => {
+            !text.is_empty()
+        }
+    });
+
+    chunks
+}
+
+fn diff_lcs(old: &[&str], new: &[&str], chunks: &mut Vec<DiffChunk>) {
+    let (n, m) = (old.len(), new.len());
+    let width = m + 1;
+
+    // lengths[i * width + j] is the LCS length of old[i..] and new[j..]
+    let mut lengths = vec![0u32; (n + 1) * width];
+
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+
+    while i < n && j < m {
+        if old[i] == new[j] {
+            push_chunk(chunks, DiffChunk::Equal(String::from(old[i])));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            push_chunk(chunks, DiffChunk::Delete(String::from(old[i])));
+            i += 1;
+        } else {
+            push_chunk(chunks, DiffChunk::Insert(String::from(new[j])));
+            j += 1;
+        }
+    }
+
+    for token in &old[i..] {
+        push_chunk(chunks, DiffChunk::Delete(String::from(*token)));
+    }
+
+    for token in &new[j..] {
+        push_chunk(chunks, DiffChunk::Insert(String::from(*token)));
+    }
+}
+
+/// Adds a chunk, merging it into the previous one if they are the same kind.
+fn push_chunk(chunks: &mut Vec<DiffChunk>, chunk: DiffChunk) {
+    if let Some(last) = chunks.last_mut() {
+        if last.same_kind(&chunk) {
+            let mut chunk = chunk;
+            last.text_mut().push_str(chunk.text_mut());
+            return;
+        }
+    }
+
+    chunks.push(chunk);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn equal(text: &str) -> DiffChunk {
+        DiffChunk::Equal(String::from(text))
+    }
+
+    fn insert(text: &str) -> DiffChunk {
+        DiffChunk::Insert(String::from(text))
+    }
+
+    fn delete(text: &str) -> DiffChunk {
+        DiffChunk::Delete(String::from(text))
+    }
+
+    /// Rebuilds the old and new texts from a diff.
+    fn apply(chunks: &[DiffChunk]) -> (String, String) {
+        let mut old = String::new();
+        let mut new = String::new();
+
+        for chunk in chunks {
+            match chunk {
+                DiffChunk::Equal(text) => {
+                    old.push_str(text);
+                    new.push_str(text);
+                }
+                DiffChunk::Delete(text) => old.push_str(text),
+                DiffChunk::Insert(text) => new.push_str(text),
+            }
+        }
+
+        (old, new)
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(diff("", "", DiffMode::Line), vec![]);
+        assert_eq!(
+            diff("a\nb\n", "a\nb\n", DiffMode::Line),
+            vec![equal("a\nb\n")],
+        );
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nx\nc\n", DiffMode::Line),
+            vec![equal("a\n"), delete("b\n"), insert("x\n"), equal("c\n")],
+        );
+
+        assert_eq!(
+            diff("", "new\ntext", DiffMode::Line),
+            vec![insert("new\ntext")],
+        );
+
+        assert_eq!(
+            diff("a\nb", "a\n", DiffMode::Line),
+            vec![equal("a\n"), delete("b")],
+        );
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(
+            diff("the quick fox", "the slow fox", DiffMode::Word),
+            vec![
+                equal("the "),
+                delete("quick"),
+                insert("slow"),
+                equal(" fox")
+            ],
+        );
+    }
+
+    #[test]
+    fn lcs() {
+        let old = "a\nb\nc\nd\ne\nf\n";
+        let new = "b\nx\nd\ne\ny\nf\nz\n";
+        let chunks = diff(old, new, DiffMode::Line);
+
+        assert_eq!(apply(&chunks), (String::from(old), String::from(new)));
+
+        // The longest common subsequence is b, d, e, f
+        let common: String = chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                DiffChunk::Equal(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(common, "b\nd\ne\nf\n");
+    }
+
+    #[test]
+    fn too_large() {
+        let lines = 2100;
+        assert!(lines * lines > MAX_DIFF_CELLS);
+
+        let mut old = String::from("header\n");
+        let mut new = String::from("header\n");
+
+        for i in 0..lines {
+            if i == lines / 2 {
+                old.push_str("common\n");
+                new.push_str("common\n");
+            } else {
+                old.push_str(&format!("old {}\n", i));
+                new.push_str(&format!("new {}\n", i));
+            }
+        }
+
+        old.push_str("footer\n");
+        new.push_str("footer\n");
+
+        // Rather than finding the common line, the middle is replaced wholesale
+        let chunks = diff(&old, &new, DiffMode::Line);
+        let old_middle = &old["header\n".len()..old.len() - "footer\n".len()];
+        let new_middle = &new["header\n".len()..new.len() - "footer\n".len()];
+
+        assert_eq!(
+            chunks,
+            vec![
+                equal("header\n"),
+                delete(old_middle),
+                insert(new_middle),
+                equal("footer\n"),
+            ],
+        );
+    }
+
+    #[test]
+    fn html() {
+        let chunks = vec![equal("a < b"), delete("&"), insert("\"c\"")];
+
+        assert_eq!(
+            diff_html(&chunks),
+            "<pre class=\"diff\">a &lt; b\
+             <del class=\"diff-delete\">&amp;</del>\
+             <ins class=\"diff-insert\">&quot;c&quot;</ins></pre>",
+        );
+    }
+}
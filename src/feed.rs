@@ -0,0 +1,370 @@
+/*
+ * feed.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Syndication feeds, in either RSS 2.0 or Atom format.
+//!
+//! Feeds carry `ETag` and `Last-Modified` headers, so feed readers polling
+//! them can get a `304 Not Modified` instead of the whole feed each time.
+//! Rendered feeds are cached per wiki until one of its pages changes.
+
+use crate::utils::escape_html;
+use chrono::{DateTime, TimeZone, Utc};
+use deepwell_core::types::WikiId;
+use futures::lock::Mutex as AsyncMutex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// The date format used in HTTP headers and RSS, as per RFC 2822.
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+}
+
+impl FeedFormat {
+    /// Gets the format from a feed's file extension, e.g. `pages.atom`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "xml" | "rss" => Some(FeedFormat::Rss),
+            "atom" => Some(FeedFormat::Atom),
+            _ => None,
+        }
+    }
+
+    /// Gets the usual file extension for this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "xml",
+            FeedFormat::Atom => "atom",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+        }
+    }
+}
+
+/// Which of a site's feeds this is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FeedKind {
+    Pages,
+    SiteChanges,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub updated: DateTime<Utc>,
+    pub author: Option<String>,
+    pub summary: String,
+}
+
+/// A feed, with absolute URLs for all links.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: String,
+    pub link: String,
+    pub self_link: String,
+    pub description: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// Gets when the feed last changed, which is when its newest entry was.
+    ///
+    /// Empty feeds are dated to the epoch, so they still have a stable date.
+    pub fn updated(&self) -> DateTime<Utc> {
+        self.entries
+            .iter()
+            .map(|entry| entry.updated)
+            .max()
+            .unwrap_or_else(|| Utc.timestamp(0, 0))
+    }
+
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Rss => self.render_rss(),
+            FeedFormat::Atom => self.render_atom(),
+        }
+    }
+
+    fn render_rss(&self) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+             xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+             <channel>\n\
+             <title>{}</title>\n\
+             <link>{}</link>\n\
+             <description>{}</description>\n\
+             <lastBuildDate>{}</lastBuildDate>\n\
+             <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\" />\n",
+            escape_html(&self.title),
+            escape_html(&self.link),
+            escape_html(&self.description),
+            http_date(self.updated()),
+            escape_html(&self.self_link),
+        );
+
+        for entry in &self.entries {
+            xml.push_str(&format!(
+                "<item>\n\
+                 <guid isPermaLink=\"false\">{}</guid>\n\
+                 <title>{}</title>\n\
+                 <link>{}</link>\n\
+                 <pubDate>{}</pubDate>\n\
+                 <description>{}</description>\n",
+                escape_html(&entry.id),
+                escape_html(&entry.title),
+                escape_html(&entry.link),
+                http_date(entry.updated),
+                escape_html(&entry.summary),
+            ));
+
+            // RSS's <author> must be an email address, so use Dublin Core for names
+            if let Some(ref author) = entry.author {
+                xml.push_str(&format!(
+                    "<dc:creator>{}</dc:creator>\n",
+                    escape_html(author)
+                ));
+            }
+
+            xml.push_str("</item>\n");
+        }
+
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+
+    fn render_atom(&self) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+             <id>{}</id>\n\
+             <title>{}</title>\n\
+             <subtitle>{}</subtitle>\n\
+             <updated>{}</updated>\n\
+             <link href=\"{}\" />\n\
+             <link href=\"{}\" rel=\"self\" />\n\
+             <author><name>{}</name></author>\n",
+            escape_html(&self.self_link),
+            escape_html(&self.title),
+            escape_html(&self.description),
+            self.updated().to_rfc3339(),
+            escape_html(&self.link),
+            escape_html(&self.self_link),
+            escape_html(&self.title),
+        );
+
+        for entry in &self.entries {
+            xml.push_str(&format!(
+                "<entry>\n\
+                 <id>{}</id>\n\
+                 <title>{}</title>\n\
+                 <link href=\"{}\" />\n\
+                 <updated>{}</updated>\n\
+                 <summary>{}</summary>\n",
+                escape_html(&entry.id),
+                escape_html(&entry.title),
+                escape_html(&entry.link),
+                entry.updated.to_rfc3339(),
+                escape_html(&entry.summary),
+            ));
+
+            if let Some(ref author) = entry.author {
+                xml.push_str(&format!(
+                    "<author><name>{}</name></author>\n",
+                    escape_html(author),
+                ));
+            }
+
+            xml.push_str("</entry>\n");
+        }
+
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+/// A feed rendered in one format, ready to serve.
+#[derive(Debug, Clone)]
+pub struct RenderedFeed {
+    pub body: String,
+    pub etag: String,
+    pub updated: DateTime<Utc>,
+}
+
+impl RenderedFeed {
+    pub fn new(feed: &Feed, format: FeedFormat) -> Self {
+        let body = feed.render(format);
+        let etag = etag(&body);
+
+        RenderedFeed {
+            body,
+            etag,
+            updated: feed.updated(),
+        }
+    }
+}
+
+struct SiteFeeds {
+    feeds: HashMap<(FeedKind, FeedFormat), Arc<RenderedFeed>>,
+    generation: u64,
+    loading: Arc<AsyncMutex<()>>,
+}
+
+impl SiteFeeds {
+    fn new() -> Self {
+        SiteFeeds {
+            feeds: HashMap::new(),
+            generation: 0,
+            loading: Arc::new(AsyncMutex::new(())),
+        }
+    }
+}
+
+/// Cache of rendered feeds, by wiki.
+#[derive(Clone, Default)]
+pub struct FeedCache {
+    sites: Arc<Mutex<HashMap<WikiId, SiteFeeds>>>,
+}
+
+impl FeedCache {
+    #[inline]
+    pub fn new() -> Self {
+        FeedCache::default()
+    }
+
+    /// Gets a cached feed.
+    /// If it isn't cached, returns the current generation, to pass to `insert` after building it.
+    pub fn get(
+        &self,
+        wiki_id: WikiId,
+        kind: FeedKind,
+        format: FeedFormat,
+    ) -> Result<Arc<RenderedFeed>, u64> {
+        let sites = self.sites.lock().expect("Feed cache lock poisoned");
+
+        match sites.get(&wiki_id) {
+            Some(site) => match site.feeds.get(&(kind, format)) {
+                Some(feed) => Ok(Arc::clone(feed)),
+                None => Err(site.generation),
+            },
+            None => Err(0),
+        }
+    }
+
+    /// Gets the lock held while building a wiki's feeds,
+    /// so that concurrent requests wait for one build instead of each starting their own.
+    pub fn load_lock(&self, wiki_id: WikiId) -> Arc<AsyncMutex<()>> {
+        let mut sites = self.sites.lock().expect("Feed cache lock poisoned");
+        let site = sites.entry(wiki_id).or_insert_with(SiteFeeds::new);
+
+        Arc::clone(&site.loading)
+    }
+
+    /// Caches a feed, built when the cache was at `generation`.
+    ///
+    /// If a page changed while it was being built, it may be out of date,
+    /// so it is returned without being cached.
+    pub fn insert(
+        &self,
+        wiki_id: WikiId,
+        kind: FeedKind,
+        format: FeedFormat,
+        generation: u64,
+        feed: RenderedFeed,
+    ) -> Arc<RenderedFeed> {
+        let feed = Arc::new(feed);
+        let mut sites = self.sites.lock().expect("Feed cache lock poisoned");
+        let site = sites.entry(wiki_id).or_insert_with(SiteFeeds::new);
+
+        if site.generation == generation {
+            site.feeds.insert((kind, format), Arc::clone(&feed));
+        } else {
+            debug!("Feeds for wiki ID {} changed while building", wiki_id);
+        }
+
+        feed
+    }
+
+    /// Removes a wiki's cached feeds, after one of its pages has changed.
+    pub fn invalidate(&self, wiki_id: WikiId) {
+        let mut sites = self.sites.lock().expect("Feed cache lock poisoned");
+        let site = sites.entry(wiki_id).or_insert_with(SiteFeeds::new);
+
+        site.feeds.clear();
+        site.generation += 1;
+    }
+}
+
+impl Debug for FeedCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FeedCache")
+            .field("sites", &"Mutex<HashMap<WikiId, SiteFeeds>>")
+            .finish()
+    }
+}
+
+/// Formats a date for the `Last-Modified` header.
+pub fn http_date(date: DateTime<Utc>) -> String {
+    date.format(HTTP_DATE_FORMAT).to_string()
+}
+
+/// Builds a strong `ETag` for a response body.
+pub fn etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Checks whether the client's cached copy is still current, from its
+/// `If-None-Match` and `If-Modified-Since` headers.
+///
+/// As per RFC 7232, `If-Modified-Since` is ignored if `If-None-Match` is present.
+pub fn not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    etag: &str,
+    updated: DateTime<Utc>,
+) -> bool {
+    if let Some(tags) = if_none_match {
+        return tags
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag == etag || tag.trim_start_matches("W/") == etag);
+    }
+
+    match if_modified_since.and_then(|date| DateTime::parse_from_rfc2822(date).ok()) {
+        Some(since) => updated.timestamp() <= since.timestamp(),
+        None => false,
+    }
+}
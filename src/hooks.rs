@@ -0,0 +1,178 @@
+/*
+ * hooks.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Keeps everything derived from a site's pages up to date as they change.
+//!
+//! Handlers which change a page call [`PageHooks::page_changed`] once DEEPWELL
+//! has saved the change, instead of updating each cache and index themselves.
+//! This must be called from within the actix runtime, since moving
+//! attached files is done in the background on the blocking thread pool.
+
+use crate::attachment::Attachments;
+use crate::cache::RenderCache;
+use crate::feed::FeedCache;
+use crate::page_list::PageListCache;
+use crate::rating::ScoreCache;
+use crate::search::SearchIndex;
+use crate::sitemap::SitemapCache;
+use crate::utils::get_wiki_slug;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use deepwell_core::types::WikiId;
+
+/// What happened to a page.
+#[derive(Debug, Copy, Clone)]
+pub enum PageChange<'a> {
+    /// The page was created, or its contents were edited.
+    Edited {
+        title: &'a str,
+        tags: &'a [String],
+        wikitext: &'a str,
+    },
+
+    /// The page's tags were changed.
+    Tagged { tags: &'a [String] },
+
+    /// The page was moved under a different parent, or made top-level.
+    Reparented,
+
+    /// The page was moved to a new slug, such as by renaming or deleting it.
+    Moved { new_slug: &'a str },
+
+    /// The page was permanently removed.
+    Removed,
+}
+
+/// The caches and indices which need to know about page changes, shared between workers.
+#[derive(Debug, Clone)]
+pub struct PageHooks {
+    cache: RenderCache,
+    page_lists: PageListCache,
+    scores: ScoreCache,
+    attachments: Attachments,
+    search: SearchIndex,
+    sitemaps: SitemapCache,
+    feeds: FeedCache,
+}
+
+impl PageHooks {
+    #[inline]
+    pub fn new(
+        cache: RenderCache,
+        page_lists: PageListCache,
+        scores: ScoreCache,
+        attachments: Attachments,
+        search: SearchIndex,
+        sitemaps: SitemapCache,
+        feeds: FeedCache,
+    ) -> Self {
+        PageHooks {
+            cache,
+            page_lists,
+            scores,
+            attachments,
+            search,
+            sitemaps,
+            feeds,
+        }
+    }
+
+    /// Updates everything which depends on a page, after it has been changed.
+    pub fn page_changed(
+        &self,
+        wiki_id: WikiId,
+        host: Option<&str>,
+        slug: &str,
+        change: PageChange,
+    ) {
+        debug!("Page '{}' changed: {:?}", slug, change);
+
+        let wiki_slug = get_wiki_slug(host).unwrap_or("");
+
+        self.page_lists.invalidate(wiki_id);
+        self.sitemaps.invalidate(wiki_id);
+        self.feeds.invalidate(wiki_id);
+
+        // Pages which include this one or are below it need to be rendered again too
+        self.cache.invalidate_page(wiki_id, slug);
+
+        match change {
+            PageChange::Edited {
+                title,
+                tags,
+                wikitext,
+            } => self.search.update(wiki_id, slug, title, tags, wikitext),
+            PageChange::Tagged { tags } => self.search.update_tags(wiki_id, slug, tags),
+            PageChange::Reparented => (),
+            PageChange::Moved { new_slug } => {
+                self.cache.invalidate_page(wiki_id, new_slug);
+                self.scores.invalidate(wiki_id, slug);
+                self.scores.invalidate(wiki_id, new_slug);
+                self.search.rename(wiki_id, slug, new_slug);
+
+                // The page has already moved, so its files must follow
+                let attachments = Attachments::clone(&self.attachments);
+                let (wiki_slug, slug, new_slug) = (
+                    String::from(wiki_slug),
+                    String::from(slug),
+                    String::from(new_slug),
+                );
+
+                spawn_file_task(move || {
+                    attachments
+                        .move_page(&wiki_slug, &slug, &new_slug)
+                        .map_err(|error| {
+                            format!(
+                                "Failed to move files from page '{}' to '{}': {}",
+                                slug, new_slug, error
+                            )
+                        })
+                });
+            }
+            PageChange::Removed => {
+                self.search.remove(wiki_id, slug);
+                self.scores.invalidate(wiki_id, slug);
+
+                let attachments = Attachments::clone(&self.attachments);
+                let (wiki_slug, slug) = (String::from(wiki_slug), String::from(slug));
+
+                spawn_file_task(move || {
+                    attachments.remove_page(&wiki_slug, &slug).map_err(|error| {
+                        format!("Failed to remove files from page '{}': {}", slug, error)
+                    })
+                });
+            }
+        }
+    }
+}
+
+/// Runs work on attached files in the blocking thread pool, logging if it fails.
+fn spawn_file_task<F>(f: F)
+where
+    F: FnOnce() -> Result<(), String> + Send + 'static,
+{
+    actix_rt::spawn(async move {
+        match web::block(f).await {
+            Ok(()) => (),
+            Err(BlockingError::Error(message)) => warn!("{}", message),
+            Err(BlockingError::Canceled) => warn!("Background file task was canceled"),
+        }
+    });
+}
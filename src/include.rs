@@ -0,0 +1,293 @@
+/*
+ * include.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Expansion of Wikidot's `[[include]]` blocks before rendering.
+//!
+//! An include such as `[[include component:license-box author=Dr Gears | year=2008]]`
+//! is replaced by the wikitext of that page, with `{$author}` and `{$year}`
+//! substituted. Included pages can themselves include others, up to a depth and
+//! total-size limit.
+//!
+//! Expansion is done in passes, since pages must be fetched from DEEPWELL:
+//! each pass reports which pages it still needs, which are fetched and given
+//! to the next pass.
+//!
+//! Includes inside literal text, such as `[[code]]` blocks and `@@raw@@` text,
+//! are left as they are, since ftml will display them instead of running them.
+
+use crate::utils::normalize_slug;
+use regex::{Captures, Regex};
+use std::collections::{BTreeSet, HashMap};
+
+/// How deeply includes can be nested.
+pub const MAX_INCLUDE_DEPTH: usize = 5;
+
+/// The most wikitext, in bytes, which can be included into a single page.
+pub const MAX_INCLUDE_SIZE: usize = 1024 * 1024;
+
+lazy_static! {
+    static ref INCLUDE_REGEX: Regex =
+        Regex::new(r"(?i)\[\[\s*include\s+([^\s\]|]+)([^\]]*)\]\]").unwrap();
+    static ref VARIABLE_REGEX: Regex = Regex::new(r"\{\$([a-zA-Z0-9_\-]+)\}").unwrap();
+    static ref LITERAL_REGEX: Regex =
+        Regex::new(r"(?is)\[\[\s*code\b[^\]]*\]\].*?\[\[/\s*code\s*\]\]|@@[^\n]*?@@").unwrap();
+}
+
+/// The wikitext of pages which can be included, by slug.
+/// A value of `None` means the page does not exist.
+pub type IncludedPages = HashMap<String, Option<String>>;
+
+/// The result of one expansion pass.
+#[derive(Debug, Clone, Default)]
+pub struct Expansion {
+    /// The wikitext with includes replaced.
+    /// This is only complete if `needed` is empty.
+    pub wikitext: String,
+
+    /// Pages which must be fetched before expansion can finish.
+    pub needed: BTreeSet<String>,
+
+    /// Included pages which don't exist.
+    pub missing: BTreeSet<String>,
+
+    /// Every page which was included, including missing ones.
+    pub includes: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    needed: BTreeSet<String>,
+    missing: BTreeSet<String>,
+    includes: BTreeSet<String>,
+    size: usize,
+}
+
+/// Expands all the includes in the given wikitext, using the pages fetched so far.
+pub fn expand(wikitext: &str, pages: &IncludedPages) -> Expansion {
+    let mut state = State::default();
+    let wikitext = expand_inner(wikitext, pages, 0, &mut state);
+
+    let State {
+        needed,
+        missing,
+        includes,
+        ..
+    } = state;
+
+    Expansion {
+        wikitext,
+        needed,
+        missing,
+        includes,
+    }
+}
+
+fn expand_inner(wikitext: &str, pages: &IncludedPages, depth: usize, state: &mut State) -> String {
+    let mut output = String::with_capacity(wikitext.len());
+    let mut last = 0;
+
+    let literals: Vec<_> = LITERAL_REGEX
+        .find_iter(wikitext)
+        .map(|mtch| mtch.start()..mtch.end())
+        .collect();
+
+    for captures in INCLUDE_REGEX.captures_iter(wikitext) {
+        let whole = captures.get(0).unwrap();
+        if literals.iter().any(|range| range.contains(&whole.start())) {
+            continue;
+        }
+
+        output.push_str(&wikitext[last..whole.start()]);
+        last = whole.end();
+
+        let slug = normalize_slug(&captures[1]);
+        state.includes.insert(slug.clone());
+
+        if depth >= MAX_INCLUDE_DEPTH {
+            output.push_str(&error_block(&format!(
+                "Include of \"{}\" is nested too deeply",
+                slug,
+            )));
+            continue;
+        }
+
+        match pages.get(&slug) {
+            None => {
+                // Fill in on the next pass
+                state.needed.insert(slug);
+            }
+            Some(None) => {
+                output.push_str(&error_block(&format!(
+                    "Included page \"{}\" does not exist ([/{}/edit/true create it now])",
+                    slug, slug,
+                )));
+
+                state.missing.insert(slug);
+            }
+            Some(Some(contents)) => {
+                state.size += contents.len();
+
+                if state.size > MAX_INCLUDE_SIZE {
+                    output.push_str(&error_block(&format!(
+                        "Include of \"{}\" exceeds the size limit",
+                        slug,
+                    )));
+                    continue;
+                }
+
+                let variables = parse_variables(&captures[2]);
+                let contents = substitute(contents, &variables);
+                let expanded = expand_inner(&contents, pages, depth + 1, state);
+                output.push_str(&expanded);
+            }
+        }
+    }
+
+    output.push_str(&wikitext[last..]);
+    output
+}
+
+/// Parses the variables passed to an include, such as ` a=1 | b = 2`.
+fn parse_variables(arguments: &str) -> HashMap<&str, &str> {
+    arguments
+        .split('|')
+        .filter_map(|argument| {
+            let mut parts = argument.splitn(2, '=');
+            let key = parts.next()?.trim();
+            let value = parts.next()?.trim();
+
+            if key.is_empty() {
+                None
+            } else {
+                Some((key, value))
+            }
+        })
+        .collect()
+}
+
+/// Replaces `{$variable}` with its value. Unknown variables are left as-is.
+fn substitute(wikitext: &str, variables: &HashMap<&str, &str>) -> String {
+    if variables.is_empty() {
+        return String::from(wikitext);
+    }
+
+    let replaced = VARIABLE_REGEX.replace_all(wikitext, |captures: &Captures| {
+        match variables.get(&captures[1]) {
+            Some(value) => String::from(*value),
+            None => String::from(&captures[0]),
+        }
+    });
+
+    replaced.into_owned()
+}
+
+fn error_block(message: &str) -> String {
+    format!("\n[[div class=\"error-block\"]]\n{}\n[[/div]]\n", message,)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pages(entries: &[(&str, Option<&str>)]) -> IncludedPages {
+        entries
+            .iter()
+            .map(|(slug, contents)| (String::from(*slug), contents.map(String::from)))
+            .collect()
+    }
+
+    #[test]
+    fn needed_pages() {
+        let expansion = expand("A [[include component:box]] B", &IncludedPages::new());
+
+        assert_eq!(
+            expansion.needed.iter().collect::<Vec<_>>(),
+            vec!["component:box"],
+        );
+        assert!(expansion.missing.is_empty());
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let pages = pages(&[("box", Some("By {$author} in {$year}, {$other}"))]);
+        let expansion = expand("[[include box author=Dr Gears | year = 2008]]", &pages);
+
+        assert!(expansion.needed.is_empty());
+        assert_eq!(expansion.wikitext, "By Dr Gears in 2008, {$other}");
+    }
+
+    #[test]
+    fn nested() {
+        let pages = pages(&[("outer", Some("<[[include inner]]>")), ("inner", Some("x"))]);
+        let expansion = expand("[[include outer]]", &pages);
+
+        assert_eq!(expansion.wikitext, "<x>");
+        assert_eq!(
+            expansion.includes.iter().collect::<Vec<_>>(),
+            vec!["inner", "outer"],
+        );
+    }
+
+    #[test]
+    fn missing_page() {
+        let pages = pages(&[("gone", None)]);
+        let expansion = expand("[[include gone]]", &pages);
+
+        assert!(expansion.needed.is_empty());
+        assert!(expansion.missing.contains("gone"));
+        assert!(expansion.wikitext.contains("does not exist"));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let pages = pages(&[("loop", Some("x[[include loop]]"))]);
+        let expansion = expand("[[include loop]]", &pages);
+
+        assert!(expansion.needed.is_empty());
+        assert_eq!(expansion.wikitext.matches('x').count(), MAX_INCLUDE_DEPTH);
+        assert!(expansion.wikitext.contains("nested too deeply"));
+    }
+
+    #[test]
+    fn size_limit() {
+        let big = "q".repeat(MAX_INCLUDE_SIZE / 2 + 1);
+        let pages = pages(&[("big", Some(&big))]);
+        let expansion = expand("[[include big]] [[include big]]", &pages);
+
+        assert_eq!(expansion.wikitext.matches('q').count(), big.len());
+        assert!(expansion.wikitext.contains("exceeds the size limit"));
+    }
+
+    #[test]
+    fn literal_text() {
+        let wikitext = "[[code]]\n[[include a]]\n[[/code]] @@[[include b]]@@ [[include c]]";
+        let expansion = expand(wikitext, &IncludedPages::new());
+
+        assert_eq!(expansion.needed.iter().collect::<Vec<_>>(), vec!["c"]);
+
+        let pages = pages(&[("c", Some("C"))]);
+        let expansion = expand(wikitext, &pages);
+
+        assert_eq!(
+            expansion.wikitext,
+            "[[code]]\n[[include a]]\n[[/code]] @@[[include b]]@@ C",
+        );
+    }
+}
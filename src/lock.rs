@@ -0,0 +1,231 @@
+/*
+ * lock.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Edit locks, which reserve a page for one user while they are editing it.
+//!
+//! Locks are leases which expire after a given duration unless renewed,
+//! so an abandoned editor does not keep a page locked forever.
+//! Expired locks are swept whenever a new lock is acquired.
+
+use crate::utils::normalize_slug;
+use deepwell_core::types::{UserId, WikiId};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+type LockKey = (WikiId, String);
+
+#[inline]
+fn lock_key(wiki_id: WikiId, slug: &str) -> LockKey {
+    (wiki_id, normalize_slug(slug))
+}
+
+/// A lock on a page held by a particular user.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EditLock {
+    pub user_id: UserId,
+    pub expires_at: SystemTime,
+}
+
+impl EditLock {
+    #[inline]
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at <= now
+    }
+
+    /// Gets the expiry time in seconds since the UNIX epoch.
+    pub fn expires_at_unix(&self) -> u64 {
+        self.expires_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("System time before epoch")
+            .as_secs()
+    }
+}
+
+/// All edit locks currently held, shared between workers.
+#[derive(Clone)]
+pub struct EditLocks {
+    locks: Arc<Mutex<HashMap<LockKey, EditLock>>>,
+    max_duration: Duration,
+}
+
+impl EditLocks {
+    /// Creates a new lock table, where no lease may be longer than `max_duration`.
+    pub fn new(max_duration: Duration) -> Self {
+        EditLocks {
+            locks: Arc::new(Mutex::new(HashMap::new())),
+            max_duration,
+        }
+    }
+
+    #[inline]
+    pub fn max_duration(&self) -> Duration {
+        self.max_duration
+    }
+
+    /// Gets the current, unexpired lock on a page, if any.
+    pub fn get(&self, wiki_id: WikiId, slug: &str) -> Option<EditLock> {
+        let now = SystemTime::now();
+        let mut locks = self.locks.lock().expect("Edit lock table poisoned");
+        let key = lock_key(wiki_id, slug);
+
+        match locks.get(&key) {
+            Some(lock) if lock.is_expired(now) => {
+                locks.remove(&key);
+                None
+            }
+            Some(lock) => Some(*lock),
+            None => None,
+        }
+    }
+
+    /// Acquires or renews a lock on a page for the given user.
+    ///
+    /// If another user holds an unexpired lock, it is returned as the error.
+    pub fn acquire(
+        &self,
+        wiki_id: WikiId,
+        slug: &str,
+        user_id: UserId,
+        duration: Duration,
+    ) -> Result<EditLock, EditLock> {
+        let now = SystemTime::now();
+        let duration = duration.min(self.max_duration);
+        let mut locks = self.locks.lock().expect("Edit lock table poisoned");
+        let key = lock_key(wiki_id, slug);
+
+        // Sweep abandoned locks, so the table only holds live ones
+        locks.retain(|_, lock| !lock.is_expired(now));
+
+        if let Some(lock) = locks.get(&key) {
+            if lock.user_id != user_id && !lock.is_expired(now) {
+                return Err(*lock);
+            }
+        }
+
+        let lock = EditLock {
+            user_id,
+            expires_at: now + duration,
+        };
+
+        locks.insert(key, lock);
+        Ok(lock)
+    }
+
+    /// Releases the user's lock on a page.
+    ///
+    /// Returns whether a lock was released, or the lock if another user holds it.
+    pub fn release(&self, wiki_id: WikiId, slug: &str, user_id: UserId) -> Result<bool, EditLock> {
+        let now = SystemTime::now();
+        let mut locks = self.locks.lock().expect("Edit lock table poisoned");
+        let key = lock_key(wiki_id, slug);
+
+        match locks.get(&key) {
+            Some(lock) if lock.is_expired(now) => {
+                locks.remove(&key);
+                Ok(false)
+            }
+            Some(lock) if lock.user_id != user_id => Err(*lock),
+            Some(_) => {
+                locks.remove(&key);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Debug for EditLocks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EditLocks")
+            .field("locks", &"Mutex<HashMap { .. }>")
+            .field("max_duration", &self.max_duration)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn locks() -> EditLocks {
+        EditLocks::new(Duration::from_secs(900))
+    }
+
+    #[test]
+    fn acquire_conflict() {
+        let locks = locks();
+        let wiki_id = WikiId::from_raw(1);
+        let alice = UserId::from_raw(1);
+        let bob = UserId::from_raw(2);
+        let duration = Duration::from_secs(60);
+
+        assert!(locks.acquire(wiki_id, "scp-173", alice, duration).is_ok());
+        assert!(locks.acquire(wiki_id, "scp-173", alice, duration).is_ok());
+        assert_eq!(
+            locks
+                .acquire(wiki_id, "SCP 173", bob, duration)
+                .map_err(|lock| lock.user_id),
+            Err(alice),
+        );
+
+        assert!(locks.release(wiki_id, "scp-173", bob).is_err());
+        assert_eq!(locks.release(wiki_id, "/SCP-173/", alice), Ok(true));
+        assert!(locks.get(wiki_id, "scp-173").is_none());
+        assert!(locks.acquire(wiki_id, "scp-173", bob, duration).is_ok());
+    }
+
+    #[test]
+    fn max_duration() {
+        let locks = locks();
+        let lock = locks
+            .acquire(
+                WikiId::from_raw(1),
+                "page",
+                UserId::from_raw(1),
+                Duration::from_secs(86400),
+            )
+            .unwrap();
+
+        assert!(lock.expires_at <= SystemTime::now() + locks.max_duration());
+    }
+
+    #[test]
+    fn sweep_expired() {
+        let locks = locks();
+        let wiki_id = WikiId::from_raw(1);
+        let user_id = UserId::from_raw(1);
+
+        for slug in &["a", "b", "c"] {
+            locks
+                .acquire(wiki_id, slug, user_id, Duration::from_secs(0))
+                .unwrap();
+        }
+
+        locks
+            .acquire(wiki_id, "d", user_id, Duration::from_secs(60))
+            .unwrap();
+
+        let table = locks.locks.lock().unwrap();
+        assert_eq!(table.len(), 1);
+        assert!(table.contains_key(&lock_key(wiki_id, "d")));
+    }
+}
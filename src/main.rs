@@ -23,10 +23,12 @@
 
 extern crate actix_files;
 extern crate actix_identity;
+extern crate actix_multipart;
 extern crate actix_ratelimit;
 extern crate actix_rt;
 extern crate actix_web;
 extern crate bytes;
+extern crate chrono;
 extern crate color_backtrace;
 extern crate deadpool;
 extern crate deepwell_core;
@@ -42,6 +44,8 @@ extern crate lazy_static;
 
 #[macro_use]
 extern crate log;
+extern crate lru;
+extern crate mime_guess;
 extern crate pretty_env_logger;
 extern crate regex;
 
@@ -57,17 +61,40 @@ mod build {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
+mod attachment;
+mod cache;
 mod config;
+mod diff;
+mod feed;
+mod hooks;
+mod include;
+mod lock;
 mod middleware;
+mod page_list;
+mod rating;
 mod remote;
 mod route;
+mod search;
 mod server;
 mod session;
+mod sitemap;
+mod tags;
+mod template;
 mod utils;
 
+use self::attachment::Attachments;
+use self::cache::RenderCache;
 use self::config::Config;
+use self::feed::FeedCache;
+use self::hooks::PageHooks;
+use self::lock::EditLocks;
+use self::page_list::PageListCache;
+use self::rating::ScoreCache;
 use self::remote::{DeepwellPool, FtmlPool};
+use self::search::SearchIndex;
 use self::server::Server;
+use self::sitemap::SitemapCache;
+use self::template::Templates;
 use std::process;
 
 pub type StdResult<T, E> = std::result::Result<T, E>;
@@ -83,10 +110,16 @@ async fn main() {
         log_level,
         ratelimit_requests,
         ratelimit_interval,
+        preview_ratelimit_requests,
+        preview_ratelimit_interval,
         cookie_secure,
         cookie_max_age,
         cookie_same_site,
         cookie_key,
+        render_cache_size,
+        edit_lock_duration,
+        upload_dir,
+        max_upload_size,
         deepwell_address,
         deepwell_timeout,
         deepwell_pool_size,
@@ -105,6 +138,25 @@ async fn main() {
         FtmlPool::connect(ftml_address, ftml_timeout, ftml_pool_size),
     );
 
+    let templates = Templates::load(&runtime.template_dir);
+    let render_cache = RenderCache::new(render_cache_size);
+    let page_lists = PageListCache::new();
+    let scores = ScoreCache::new();
+    let edit_locks = EditLocks::new(edit_lock_duration);
+    let attachments = Attachments::new(upload_dir, max_upload_size);
+    let search_index = SearchIndex::new();
+    let sitemaps = SitemapCache::new();
+    let feeds = FeedCache::new();
+    let page_hooks = PageHooks::new(
+        render_cache.clone(),
+        page_lists.clone(),
+        scores.clone(),
+        attachments.clone(),
+        search_index.clone(),
+        sitemaps.clone(),
+        feeds.clone(),
+    );
+
     info!("HTTP server starting on {}", http_address);
 
     let server = Server {
@@ -113,12 +165,24 @@ async fn main() {
         keep_alive,
         ratelimit_requests,
         ratelimit_interval,
+        preview_ratelimit_requests,
+        preview_ratelimit_interval,
         cookie_secure,
         cookie_max_age,
         cookie_same_site,
         cookie_key,
         deepwell,
         ftml,
+        templates,
+        render_cache,
+        page_lists,
+        scores,
+        edit_locks,
+        attachments,
+        search_index,
+        sitemaps,
+        feeds,
+        page_hooks,
     };
 
     if let Err(error) = server.run(runtime).await {
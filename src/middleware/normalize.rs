@@ -30,6 +30,10 @@ use regex::Regex;
 use std::task::{Context, Poll};
 use wikidot_path::redirect;
 
+/// Paths which are served as-is, such as page attachments, which may
+/// have filenames that aren't in Wikidot normal form.
+const UNNORMALIZED_PREFIXES: [&str; 2] = ["/local--files/", "/printer--friendly/"];
+
 lazy_static! {
     static ref STATIC_FILE_PATH: Regex = Regex::new(r"\w+\.\w+").unwrap();
 }
@@ -85,6 +89,15 @@ where
             return Either::Left(self.service.call(req));
         }
 
+        // Don't apply to special paths
+        if UNNORMALIZED_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+        {
+            trace!("Not normalizing special path");
+            return Either::Left(self.service.call(req));
+        }
+
         // See if the URL needs normalization
         match redirect(path) {
             None => Either::Left(self.service.call(req)),
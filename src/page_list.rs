@@ -0,0 +1,118 @@
+/*
+ * page_list.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Cache of the list of every page in each wiki.
+//!
+//! Tag listings and other views of a whole site are built from this list,
+//! rather than fetching every page from DEEPWELL on each request.
+//! A wiki's list is dropped whenever one of its pages changes.
+
+use deepwell_core::types::{Page, WikiId};
+use futures::lock::Mutex as AsyncMutex;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+
+struct Entry {
+    pages: Option<Arc<Vec<Page>>>,
+    generation: u64,
+    loading: Arc<AsyncMutex<()>>,
+}
+
+impl Entry {
+    fn new() -> Self {
+        Entry {
+            pages: None,
+            generation: 0,
+            loading: Arc::new(AsyncMutex::new(())),
+        }
+    }
+}
+
+/// The cached page lists for each wiki, shared between workers.
+#[derive(Clone, Default)]
+pub struct PageListCache {
+    wikis: Arc<Mutex<HashMap<WikiId, Entry>>>,
+}
+
+impl PageListCache {
+    #[inline]
+    pub fn new() -> Self {
+        PageListCache::default()
+    }
+
+    /// Gets a wiki's cached page list.
+    /// If it isn't cached, returns the current generation, to pass to `insert` after loading it.
+    pub fn get(&self, wiki_id: WikiId) -> Result<Arc<Vec<Page>>, u64> {
+        let wikis = self.wikis.lock().expect("Page list cache lock poisoned");
+
+        match wikis.get(&wiki_id) {
+            Some(Entry {
+                pages: Some(pages), ..
+            }) => Ok(Arc::clone(pages)),
+            Some(entry) => Err(entry.generation),
+            None => Err(0),
+        }
+    }
+
+    /// Gets the lock held while loading a wiki's page list,
+    /// so that concurrent requests wait for one load instead of each starting their own.
+    pub fn load_lock(&self, wiki_id: WikiId) -> Arc<AsyncMutex<()>> {
+        let mut wikis = self.wikis.lock().expect("Page list cache lock poisoned");
+        let entry = wikis.entry(wiki_id).or_insert_with(Entry::new);
+
+        Arc::clone(&entry.loading)
+    }
+
+    /// Caches a wiki's page list, loaded when the cache was at `generation`.
+    ///
+    /// If a page changed while it was loading, the list may be out of date,
+    /// so it is returned without being cached.
+    pub fn insert(&self, wiki_id: WikiId, generation: u64, pages: Vec<Page>) -> Arc<Vec<Page>> {
+        let pages = Arc::new(pages);
+        let mut wikis = self.wikis.lock().expect("Page list cache lock poisoned");
+        let entry = wikis.entry(wiki_id).or_insert_with(Entry::new);
+
+        if entry.generation == generation {
+            entry.pages = Some(Arc::clone(&pages));
+        } else {
+            debug!("Page list for wiki ID {} changed while loading", wiki_id);
+        }
+
+        pages
+    }
+
+    /// Drops a wiki's cached page list, after one of its pages has changed.
+    pub fn invalidate(&self, wiki_id: WikiId) {
+        let mut wikis = self.wikis.lock().expect("Page list cache lock poisoned");
+        let entry = wikis.entry(wiki_id).or_insert_with(Entry::new);
+
+        entry.pages = None;
+        entry.generation += 1;
+    }
+}
+
+impl Debug for PageListCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PageListCache")
+            .field("wikis", &"Mutex<HashMap<WikiId, Entry>>")
+            .finish()
+    }
+}
@@ -0,0 +1,245 @@
+/*
+ * rating.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Page votes and the ratings aggregated from them.
+//!
+//! Members may upvote (+1), downvote (-1), or cast a neutral vote (0),
+//! which is counted as a vote but does not change the score.
+//!
+//! Page scores are cached, since page queries can filter and sort on them,
+//! and DEEPWELL only gives the votes of one page at a time.
+
+use deepwell_core::types::{UserId, WikiId};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+
+/// A vote cast on a page by a user.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum VoteValue {
+    Up,
+    Neutral,
+    Down,
+}
+
+impl VoteValue {
+    /// The value of this vote as stored in DEEPWELL.
+    #[inline]
+    pub fn value(self) -> i8 {
+        match self {
+            VoteValue::Up => 1,
+            VoteValue::Neutral => 0,
+            VoteValue::Down => -1,
+        }
+    }
+}
+
+impl TryFrom<i8> for VoteValue {
+    type Error = i8;
+
+    fn try_from(value: i8) -> Result<Self, i8> {
+        match value {
+            1 => Ok(VoteValue::Up),
+            0 => Ok(VoteValue::Neutral),
+            -1 => Ok(VoteValue::Down),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The aggregated votes on a page.
+#[derive(Serialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Rating {
+    pub score: i32,
+    pub upvotes: u32,
+    pub neutral: u32,
+    pub downvotes: u32,
+}
+
+impl Rating {
+    pub fn from_votes(votes: &[(UserId, i8)]) -> Self {
+        let mut rating = Rating::default();
+
+        for &(user_id, value) in votes {
+            match VoteValue::try_from(value) {
+                Ok(VoteValue::Up) => rating.upvotes += 1,
+                Ok(VoteValue::Neutral) => rating.neutral += 1,
+                Ok(VoteValue::Down) => rating.downvotes += 1,
+                Err(value) => {
+                    warn!(
+                        "Ignoring invalid vote value {} by user ID {}",
+                        value, user_id
+                    );
+                    continue;
+                }
+            }
+
+            rating.score += i32::from(value);
+        }
+
+        rating
+    }
+
+    /// Total number of votes cast, including neutral ones.
+    #[inline]
+    pub fn votes(&self) -> u32 {
+        self.upvotes + self.neutral + self.downvotes
+    }
+
+    /// The score formatted as shown on pages, for instance `+5` or `-2`.
+    pub fn display(&self) -> String {
+        if self.score > 0 {
+            format!("+{}", self.score)
+        } else {
+            self.score.to_string()
+        }
+    }
+}
+
+#[derive(Default)]
+struct WikiScores {
+    scores: HashMap<String, i32>,
+    generation: u64,
+}
+
+/// The cached scores of pages in each wiki, shared between workers.
+#[derive(Clone, Default)]
+pub struct ScoreCache {
+    wikis: Arc<Mutex<HashMap<WikiId, WikiScores>>>,
+}
+
+impl ScoreCache {
+    #[inline]
+    pub fn new() -> Self {
+        ScoreCache::default()
+    }
+
+    /// Adds the cached scores of the given pages to `scores`.
+    /// Returns the current generation, to pass to `insert` after fetching the rest.
+    pub fn get<'a, I>(&self, wiki_id: WikiId, slugs: I, scores: &mut HashMap<String, i32>) -> u64
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let wikis = self.wikis.lock().expect("Score cache lock poisoned");
+        let wiki = match wikis.get(&wiki_id) {
+            Some(wiki) => wiki,
+            None => return 0,
+        };
+
+        for slug in slugs {
+            if let Some(&score) = wiki.scores.get(slug) {
+                scores.insert(String::from(slug), score);
+            }
+        }
+
+        wiki.generation
+    }
+
+    /// Caches scores fetched from DEEPWELL when the cache was at `generation`.
+    ///
+    /// If a vote was cast since, these may be out of date, so they are not cached.
+    pub fn insert(&self, wiki_id: WikiId, generation: u64, scores: &[(String, i32)]) {
+        let mut wikis = self.wikis.lock().expect("Score cache lock poisoned");
+        let wiki = wikis.entry(wiki_id).or_default();
+
+        if wiki.generation != generation {
+            debug!("Scores for wiki ID {} changed while fetching", wiki_id);
+            return;
+        }
+
+        for (slug, score) in scores {
+            wiki.scores.insert(slug.clone(), *score);
+        }
+    }
+
+    /// Sets a page's score, after its votes have changed.
+    pub fn set(&self, wiki_id: WikiId, slug: &str, score: i32) {
+        let mut wikis = self.wikis.lock().expect("Score cache lock poisoned");
+        let wiki = wikis.entry(wiki_id).or_default();
+
+        wiki.scores.insert(String::from(slug), score);
+        wiki.generation += 1;
+    }
+
+    /// Drops a page's cached score, such as when it is moved.
+    pub fn invalidate(&self, wiki_id: WikiId, slug: &str) {
+        let mut wikis = self.wikis.lock().expect("Score cache lock poisoned");
+        let wiki = wikis.entry(wiki_id).or_default();
+
+        wiki.scores.remove(slug);
+        wiki.generation += 1;
+    }
+}
+
+impl Debug for ScoreCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScoreCache")
+            .field("wikis", &"Mutex<HashMap<WikiId, WikiScores>>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scores() {
+        let cache = ScoreCache::new();
+        let wiki_id = WikiId::from_raw(1);
+        let mut scores = HashMap::new();
+
+        let generation = cache.get(wiki_id, vec!["a", "b"], &mut scores);
+        assert!(scores.is_empty());
+
+        cache.insert(wiki_id, generation, &[(String::from("a"), 5)]);
+        cache.get(wiki_id, vec!["a", "b"], &mut scores);
+        assert_eq!(scores.get("a"), Some(&5));
+        assert_eq!(scores.get("b"), None);
+
+        // Other wikis are separate
+        scores.clear();
+        cache.get(WikiId::from_raw(2), vec!["a"], &mut scores);
+        assert!(scores.is_empty());
+    }
+
+    #[test]
+    fn stale_insert() {
+        let cache = ScoreCache::new();
+        let wiki_id = WikiId::from_raw(1);
+        let mut scores = HashMap::new();
+
+        // A vote comes in while a query is fetching
+        let generation = cache.get(wiki_id, vec!["a"], &mut scores);
+        cache.set(wiki_id, "a", 3);
+        cache.insert(wiki_id, generation, &[(String::from("a"), 2)]);
+
+        cache.get(wiki_id, vec!["a"], &mut scores);
+        assert_eq!(scores.get("a"), Some(&3));
+
+        cache.invalidate(wiki_id, "a");
+        scores.clear();
+        cache.get(wiki_id, vec!["a"], &mut scores);
+        assert!(scores.is_empty());
+    }
+}
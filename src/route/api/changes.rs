@@ -0,0 +1,84 @@
+/*
+ * route/api/changes.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::super::changes::{get_recent_changes, ChangeFilter, ChangeType};
+use super::prelude::*;
+
+const DEFAULT_CHANGES_LIMIT: u32 = 20;
+const MAX_CHANGES_LIMIT: u32 = 100;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecentChangesInput {
+    category: Option<String>,
+    user: Option<String>,
+    #[serde(rename = "type")]
+    change_type: Option<ChangeType>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+pub async fn api_recent_changes(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<RecentChangesInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /recent-changes");
+
+    let host = get_host(&req);
+    let RecentChangesInput {
+        category,
+        user,
+        change_type,
+        limit,
+        offset,
+    } = arg.into_inner();
+
+    let limit = limit
+        .unwrap_or(DEFAULT_CHANGES_LIMIT)
+        .max(1)
+        .min(MAX_CHANGES_LIMIT);
+    let offset = offset.unwrap_or(0);
+    let filter = ChangeFilter {
+        category,
+        user,
+        change_type,
+    };
+
+    let mut deepwell = deepwell.claim().await;
+    let role = try_resp!(get_role(id, host, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    let result = try_resp!(
+        get_recent_changes(
+            wiki_id,
+            role,
+            &filter,
+            offset,
+            limit,
+            &settings,
+            &mut deepwell,
+        )
+        .await
+    );
+
+    HttpResponse::Ok().json(Success::from(result))
+}
@@ -25,19 +25,21 @@ mod prelude {
     pub use actix_identity::Identity;
     pub use deepwell_core::prelude::*;
     pub use deepwell_rpc::Api as _;
+    pub use deepwell_rpc::Client as DeepwellClient;
     pub use ftml_rpc::Api as _;
 }
 
-#[macro_use]
-mod macros;
-
 mod auth;
+mod changes;
 mod misc;
 mod page;
+mod search;
 mod types;
 mod user;
 
 pub use self::auth::*;
+pub use self::changes::*;
 pub use self::misc::*;
 pub use self::page::*;
+pub use self::search::*;
 pub use self::user::*;
@@ -0,0 +1,375 @@
+/*
+ * route/api/page/delete.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Deleting pages, following Wikidot's approach.
+//!
+//! Deleted pages are moved into the `deleted:` category, keeping their history,
+//! from where moderators can restore them. Only admins can remove a page entirely.
+//!
+//! A deleted page's slug also has its page ID, as in `deleted:42:scp-173`,
+//! so that a page can be deleted again after being recreated.
+
+use super::edit::page_commit;
+use super::lock::lock_conflict;
+use super::prelude::*;
+use super::rename::move_page;
+use crate::hooks::{PageChange, PageHooks};
+use crate::lock::EditLocks;
+use crate::page_list::PageListCache;
+use deepwell_core::roles::Role;
+
+/// The category deleted pages are moved into.
+pub const DELETED_CATEGORY: &str = "deleted";
+
+/// The start of every deleted page's slug.
+const DELETED_PREFIX: &str = "deleted:";
+
+/// The role needed to delete pages, and to list or restore deleted pages.
+const DELETE_ROLE: Role = Role::Moderator;
+
+/// The role needed to permanently remove a page.
+const PURGE_ROLE: Role = Role::Admin;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageDeleteInput {
+    slug: String,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageDeleteOutput {
+    slug: String,
+    deleted_slug: String,
+    revision_id: RevisionId,
+}
+
+/// Restores a deleted page.
+/// If `new_slug` is `None`, it is restored to where it was before being deleted.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageRestoreInput {
+    slug: String,
+    new_slug: Option<String>,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageRestoreOutput {
+    deleted_slug: String,
+    slug: String,
+    revision_id: RevisionId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeletedPageOutput {
+    deleted_slug: String,
+    original_slug: String,
+    title: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PagePurgeOutput {
+    slug: String,
+}
+
+/// Gets the slug a page is moved to when it is deleted.
+#[inline]
+fn deleted_slug(slug: &str, page_id: PageId) -> String {
+    format!("{}:{}:{}", DELETED_CATEGORY, page_id, slug)
+}
+
+/// Gets the original slug of a deleted page, or `None` if it's not deleted.
+fn original_slug(slug: &str) -> Option<&str> {
+    let rest = slug.strip_prefix(DELETED_PREFIX)?;
+    let mut parts = rest.splitn(2, ':');
+
+    let original = match (parts.next(), parts.next()) {
+        (Some(page_id), Some(original)) if page_id.parse::<i64>().is_ok() => original,
+        _ => rest,
+    };
+
+    if original.is_empty() {
+        None
+    } else {
+        Some(original)
+    }
+}
+
+fn not_deleted(slug: &str) -> HttpResponse {
+    debug!("Page '{}' is not in the deleted category", slug);
+
+    let error = Error::StaticMsg("Page is not deleted").to_sendable();
+
+    HttpResponse::BadRequest().json(error)
+}
+
+pub async fn api_page_delete(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PageDeleteInput>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/delete");
+
+    let host = get_host(&req);
+    let PageDeleteInput { slug, comment } = arg.into_inner();
+    let slug = normalize_slug(&slug);
+
+    if original_slug(&slug).is_some() {
+        let error = Error::StaticMsg("Page is already deleted").to_sendable();
+
+        return HttpResponse::BadRequest().json(error);
+    }
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(DELETE_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    let page = try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    // Don't delete a page someone else is editing
+    if let Some(lock) = locks.get(wiki_id, &slug) {
+        if lock.user_id != user_id {
+            return lock_conflict(&slug, lock, &mut deepwell).await;
+        }
+    }
+
+    let deleted_slug = deleted_slug(&slug, page.id());
+    let revision_id = try_resp!(
+        move_page(
+            wiki_id,
+            &slug,
+            &deleted_slug,
+            &comment,
+            user_id,
+            &mut deepwell
+        )
+        .await
+    );
+
+    info!(
+        "Deleted page '{}' (revision {}), user ID {}",
+        slug, revision_id, user_id
+    );
+
+    let _ = locks.release(wiki_id, &slug, user_id);
+    hooks.page_changed(
+        wiki_id,
+        host,
+        &slug,
+        PageChange::Moved {
+            new_slug: &deleted_slug,
+        },
+    );
+
+    let result = PageDeleteOutput {
+        slug,
+        deleted_slug,
+        revision_id,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_deleted(
+    req: HttpRequest,
+    id: Identity,
+    deepwell: web::Data<DeepwellPool>,
+    page_lists: web::Data<PageListCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/deleted");
+
+    let host = get_host(&req);
+
+    let mut deepwell = deepwell.claim().await;
+    let role = try_resp!(get_role(id, host, &mut deepwell).await);
+    if role < DELETE_ROLE {
+        let error = Error::InsufficientPermissions(role, DELETE_ROLE).to_sendable();
+
+        return HttpResponse::Forbidden().json(error);
+    }
+
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    let pages = try_resp!(get_cached_pages(wiki_id, &page_lists, &mut deepwell).await);
+
+    // Deleted pages from restricted categories stay restricted
+    let mut result: Vec<DeletedPageOutput> = pages
+        .iter()
+        .filter(|page| read_role(&settings, page.slug()) <= role)
+        .filter_map(|page| {
+            let original = original_slug(page.slug())?;
+
+            Some(DeletedPageOutput {
+                deleted_slug: String::from(page.slug()),
+                original_slug: String::from(original),
+                title: String::from(page.title()),
+            })
+        })
+        .collect();
+
+    result.sort_by(|a, b| a.deleted_slug.cmp(&b.deleted_slug));
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_restore(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PageRestoreInput>,
+    deepwell: web::Data<DeepwellPool>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/restore");
+
+    let host = get_host(&req);
+    let PageRestoreInput {
+        slug,
+        new_slug,
+        comment,
+    } = arg.into_inner();
+
+    let new_slug = match (original_slug(&slug), new_slug) {
+        (None, _) => return not_deleted(&slug),
+        (Some(_), Some(new_slug)) => normalize_slug(&new_slug),
+        (Some(original), None) => String::from(original),
+    };
+
+    if new_slug.is_empty() || original_slug(&new_slug).is_some() {
+        let error = Error::StaticMsg("Invalid new page name").to_sendable();
+
+        return HttpResponse::BadRequest().json(error);
+    }
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(DELETE_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    try_resp!(check_read(&new_slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    let revision_id =
+        try_resp!(move_page(wiki_id, &slug, &new_slug, &comment, user_id, &mut deepwell).await);
+
+    info!(
+        "Restored page '{}' to '{}' (revision {}), user ID {}",
+        slug, new_slug, revision_id, user_id
+    );
+
+    hooks.page_changed(
+        wiki_id,
+        host,
+        &slug,
+        PageChange::Moved {
+            new_slug: &new_slug,
+        },
+    );
+
+    let result = PageRestoreOutput {
+        deleted_slug: slug,
+        slug: new_slug,
+        revision_id,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_purge(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PageDeleteInput>,
+    deepwell: web::Data<DeepwellPool>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/purge");
+
+    let host = get_host(&req);
+    let PageDeleteInput { slug, comment } = arg.into_inner();
+
+    // Only pages which were already deleted can be purged
+    if original_slug(&slug).is_none() {
+        return not_deleted(&slug);
+    }
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(PURGE_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    let commit = page_commit(wiki_id, &slug, &comment, user_id);
+    let result = deepwell.remove_page(commit).await;
+
+    match try_io!(result) {
+        Ok(()) => {
+            info!("Purged page '{}', user ID {}", slug, user_id);
+
+            hooks.page_changed(wiki_id, host, &slug, PageChange::Removed);
+
+            let result = PagePurgeOutput { slug };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(error) => {
+            warn!("Failed to purge page '{}': {}", slug, error);
+
+            HttpResponse::InternalServerError().json(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn original_slugs() {
+        assert_eq!(original_slug("deleted"), None);
+        assert_eq!(original_slug("deleted:"), None);
+        assert_eq!(original_slug("deleted:42:"), None);
+        assert_eq!(original_slug("deleted:foo"), Some("foo"));
+        assert_eq!(original_slug("deleted:42:scp-173"), Some("scp-173"));
+        assert_eq!(original_slug("deleted:x:scp-173"), Some("x:scp-173"));
+        assert_eq!(original_slug("scp-173"), None);
+        assert_eq!(original_slug("deletedfoo"), None);
+    }
+
+    #[test]
+    fn deleted_slugs() {
+        let slug = deleted_slug("scp-173", PageId::from_raw(42));
+
+        assert_eq!(slug, "deleted:42:scp-173");
+        assert_eq!(original_slug(&slug), Some("scp-173"));
+    }
+}
@@ -0,0 +1,314 @@
+/*
+ * route/api/page/edit.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::lock::lock_conflict;
+use super::prelude::*;
+use super::rename::clear_redirect;
+use crate::hooks::{PageChange, PageHooks};
+use crate::lock::EditLocks;
+use deepwell_core::roles::Role;
+
+/// The role needed to create or edit pages.
+pub const EDIT_ROLE: Role = Role::Member;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageCreateInput {
+    slug: String,
+    title: String,
+    alt_title: Option<String>,
+    wikitext: String,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageCreateOutput {
+    slug: String,
+    page_id: PageId,
+    revision_id: RevisionId,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageEditInput {
+    slug: String,
+    title: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    alt_title: Option<Option<String>>,
+    wikitext: String,
+    #[serde(default)]
+    comment: String,
+    base_revision: RevisionId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageEditOutput {
+    slug: String,
+    revision_id: RevisionId,
+}
+
+/// Builds the commit information DEEPWELL needs for page changes.
+pub fn page_commit(wiki_id: WikiId, slug: &str, message: &str, user_id: UserId) -> PageCommit {
+    PageCommit {
+        wiki_id,
+        slug: String::from(slug),
+        message: String::from(message),
+        user_id,
+    }
+}
+
+pub async fn api_page_create(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PageCreateInput>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/create");
+
+    let host = get_host(&req);
+    let PageCreateInput {
+        slug,
+        title,
+        alt_title,
+        wikitext,
+        comment,
+    } = arg.into_inner();
+
+    let slug = normalize_slug(&slug);
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(EDIT_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    // Hold the edit lock while saving, so nobody else can create the page
+    // between checking that it doesn't exist and creating it
+    let held = locks.get(wiki_id, &slug).is_some();
+    if let Err(lock) = locks.acquire(wiki_id, &slug, user_id, locks.max_duration()) {
+        return lock_conflict(&slug, lock, &mut deepwell).await;
+    }
+
+    let release = || {
+        if !held {
+            let _ = locks.release(wiki_id, &slug, user_id);
+        }
+    };
+
+    // Check if the page already exists
+    let result = deepwell.get_page(wiki_id, slug.clone()).await;
+    match result {
+        Ok(Ok(None)) => (),
+        Ok(Ok(Some(_))) => {
+            debug!("Cannot create page '{}', it already exists", slug);
+
+            release();
+
+            let error = Error::StaticMsg("Page already exists").to_sendable();
+            return HttpResponse::Conflict().json(error);
+        }
+        Ok(Err(error)) => {
+            release();
+
+            return HttpResponse::InternalServerError().json(error);
+        }
+        Err(error) => {
+            release();
+
+            let error = Error::ServiceTransport(error).to_sendable();
+            return HttpResponse::BadGateway().json(error);
+        }
+    }
+
+    // Create page
+    let commit = page_commit(wiki_id, &slug, &comment, user_id);
+    let result = deepwell
+        .create_page(
+            commit,
+            wikitext.clone(),
+            Vec::new(),
+            title.clone(),
+            alt_title,
+        )
+        .await;
+
+    // Keep the editor's own lock if saving failed, so they can try again
+    if !matches!(result, Ok(Ok(_))) {
+        release();
+    }
+
+    match try_io!(result) {
+        Ok((page_id, revision_id)) => {
+            info!(
+                "Created page '{}' (ID {}), user ID {}",
+                slug, page_id, user_id
+            );
+
+            let _ = locks.release(wiki_id, &slug, user_id);
+
+            clear_redirect(wiki_id, &slug, &mut deepwell).await;
+
+            let change = PageChange::Edited {
+                title: &title,
+                tags: &[],
+                wikitext: &wikitext,
+            };
+
+            hooks.page_changed(wiki_id, host, &slug, change);
+
+            let result = PageCreateOutput {
+                slug,
+                page_id,
+                revision_id,
+            };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(error) => {
+            warn!("Failed to create page '{}': {}", slug, error);
+
+            HttpResponse::InternalServerError().json(error)
+        }
+    }
+}
+
+pub async fn api_page_edit(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PageEditInput>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/edit");
+
+    let host = get_host(&req);
+    let PageEditInput {
+        slug,
+        title,
+        alt_title,
+        wikitext,
+        comment,
+        base_revision,
+    } = arg.into_inner();
+
+    let slug = normalize_slug(&slug);
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(EDIT_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    // Hold the edit lock while saving, so nobody else can change the page
+    // between checking the base revision and saving over it
+    let held = locks.get(wiki_id, &slug).is_some();
+    if let Err(lock) = locks.acquire(wiki_id, &slug, user_id, locks.max_duration()) {
+        return lock_conflict(&slug, lock, &mut deepwell).await;
+    }
+
+    let release = || {
+        if !held {
+            let _ = locks.release(wiki_id, &slug, user_id);
+        }
+    };
+
+    // Check that the page hasn't changed since the editor was opened
+    let result = deepwell.get_page(wiki_id, slug.clone()).await;
+    let page = match result {
+        Ok(Ok(Some((page, _)))) => page,
+        Ok(Ok(None)) => {
+            release();
+
+            let error = Error::StaticMsg("Page does not exist").to_sendable();
+            return HttpResponse::NotFound().json(error);
+        }
+        Ok(Err(error)) => {
+            release();
+
+            return HttpResponse::InternalServerError().json(error);
+        }
+        Err(error) => {
+            release();
+
+            let error = Error::ServiceTransport(error).to_sendable();
+            return HttpResponse::BadGateway().json(error);
+        }
+    };
+
+    if page.revision_id() != base_revision {
+        debug!(
+            "Edit conflict on '{}', base revision {} but currently {}",
+            slug,
+            base_revision,
+            page.revision_id(),
+        );
+
+        release();
+
+        let error = Error::StaticMsg("Page was changed since editing began").to_sendable();
+        return HttpResponse::Conflict().json(error);
+    }
+
+    // Save changes
+    let new_title = title.clone().unwrap_or_else(|| String::from(page.title()));
+    let commit = page_commit(wiki_id, &slug, &comment, user_id);
+    let result = deepwell
+        .edit_page(commit, Some(wikitext.clone()), title, alt_title)
+        .await;
+
+    // Keep the editor's own lock if saving failed, so they can try again
+    if !matches!(result, Ok(Ok(_))) {
+        release();
+    }
+
+    match try_io!(result) {
+        Ok(revision_id) => {
+            info!(
+                "Edited page '{}' (revision {}), user ID {}",
+                slug, revision_id, user_id
+            );
+
+            let _ = locks.release(wiki_id, &slug, user_id);
+
+            let change = PageChange::Edited {
+                title: &new_title,
+                tags: page.tags(),
+                wikitext: &wikitext,
+            };
+
+            hooks.page_changed(wiki_id, host, &slug, change);
+
+            let result = PageEditOutput { slug, revision_id };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(error) => {
+            warn!("Failed to edit page '{}': {}", slug, error);
+
+            HttpResponse::InternalServerError().json(error)
+        }
+    }
+}
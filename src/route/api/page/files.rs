@@ -0,0 +1,470 @@
+/*
+ * route/api/page/files.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::edit::EDIT_ROLE;
+use super::lock::lock_conflict;
+use super::prelude::*;
+use crate::attachment::{is_valid_filename, Attachments};
+use crate::lock::EditLocks;
+use actix_multipart::Multipart;
+use actix_web::error::BlockingError;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use std::io;
+
+/// Most fields, such as files, which can be sent in one upload request.
+/// Together with the size limit for each field, this bounds the size of the whole request.
+const MAX_UPLOAD_FIELDS: usize = 16;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FilesInput {
+    slug: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileInput {
+    slug: String,
+    filename: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileRenameInput {
+    slug: String,
+    filename: String,
+    new_filename: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileOutput {
+    filename: String,
+    url: String,
+    size: u64,
+    content_type: String,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl FileOutput {
+    fn new(slug: &str, filename: String, size: u64, modified: Option<DateTime<Utc>>) -> Self {
+        let url = format!(
+            "/local--files/{}/{}",
+            encode_path_segment(slug),
+            encode_path_segment(&filename),
+        );
+        let content_type = mime_guess::from_path(&filename)
+            .first_or_octet_stream()
+            .to_string();
+
+        FileOutput {
+            filename,
+            url,
+            size,
+            content_type,
+            modified,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileRenameOutput {
+    slug: String,
+    filename: String,
+    new_filename: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileDeleteOutput {
+    slug: String,
+    filename: String,
+    deleted: bool,
+}
+
+/// The file which is larger than allowed, sent as the data of an error.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileTooLarge {
+    filename: String,
+    max_size: usize,
+}
+
+/// Gets the slug of the wiki a request is for, as attachments are stored by it.
+#[inline]
+fn wiki_slug(host: Option<&str>) -> &str {
+    get_wiki_slug(host).unwrap_or("")
+}
+
+/// Runs work on the attachment store in the blocking thread pool,
+/// since it reads and writes files on disk.
+async fn blocking<F, T>(slug: &str, f: F) -> StdResult<T, HttpResponse>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match web::block(f).await {
+        Ok(value) => Ok(value),
+        Err(BlockingError::Error(error)) => Err(file_error(slug, error)),
+        Err(BlockingError::Canceled) => {
+            let error = io::Error::new(io::ErrorKind::Other, "Blocking task canceled");
+
+            Err(file_error(slug, error))
+        }
+    }
+}
+
+/// Checks that the user may change a page's files, and that the page exists.
+///
+/// Files can't be changed on a page someone else is editing.
+async fn check_files_change(
+    slug: &str,
+    id: &Identity,
+    host: Option<&str>,
+    locks: &EditLocks,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<UserId, HttpResponse> {
+    check_role(EDIT_ROLE, id.clone(), host, deepwell).await?;
+    check_read(slug, id.clone(), host, settings, deepwell).await?;
+    let user_id = get_user_id(id, deepwell).await?;
+    let wiki_id = get_wiki_id(host, deepwell).await?;
+    get_existing_page(wiki_id, slug, deepwell).await?;
+
+    if let Some(lock) = locks.get(wiki_id, slug) {
+        if lock.user_id != user_id {
+            return Err(lock_conflict(slug, lock, deepwell).await);
+        }
+    }
+
+    Ok(user_id)
+}
+
+/// Converts an error from the attachment store into a response.
+fn file_error(slug: &str, error: io::Error) -> HttpResponse {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => {
+            let error = Error::StaticMsg("Invalid filename").to_sendable();
+
+            HttpResponse::BadRequest().json(error)
+        }
+        io::ErrorKind::AlreadyExists => {
+            let error = Error::StaticMsg("File already exists").to_sendable();
+
+            HttpResponse::Conflict().json(error)
+        }
+        _ => {
+            error!("Failed to access files for page '{}': {}", slug, error);
+
+            let error = Error::StaticMsg("Unable to access attached files").to_sendable();
+
+            HttpResponse::InternalServerError().json(error)
+        }
+    }
+}
+
+pub async fn api_page_files_list(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<FilesInput>,
+    deepwell: web::Data<DeepwellPool>,
+    attachments: web::Data<Attachments>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/files [GET]");
+
+    let host = get_host(&req);
+    let FilesInput { slug } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id, host, &settings, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    let files = {
+        let attachments = Attachments::clone(&attachments);
+        let wiki_slug = String::from(wiki_slug(host));
+        let page_slug = slug.clone();
+
+        try_resp!(blocking(&slug, move || attachments.list(&wiki_slug, &page_slug)).await)
+    };
+
+    let result: Vec<FileOutput> = files
+        .into_iter()
+        .map(|file| FileOutput::new(&slug, file.name, file.size, Some(file.modified.into())))
+        .collect();
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_files_upload(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<FilesInput>,
+    mut payload: Multipart,
+    deepwell: web::Data<DeepwellPool>,
+    attachments: web::Data<Attachments>,
+    locks: web::Data<EditLocks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/files [POST]");
+
+    let host = get_host(&req);
+    let FilesInput { slug } = arg.into_inner();
+
+    // Don't hold a DEEPWELL client while the files are being received
+    let user_id = {
+        let mut deepwell = deepwell.claim().await;
+
+        try_resp!(check_files_change(&slug, &id, host, &locks, &settings, &mut deepwell).await)
+    };
+
+    let max_size = attachments.max_size();
+    let mut fields = 0;
+    let mut result = Vec::new();
+
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(error) => {
+                warn!("Invalid multipart upload for page '{}': {}", slug, error);
+
+                let error = Error::StaticMsg("Invalid file upload").to_sendable();
+                return HttpResponse::BadRequest().json(error);
+            }
+        };
+
+        fields += 1;
+        if fields > MAX_UPLOAD_FIELDS {
+            debug!(
+                "Upload for '{}' has more than {} fields",
+                slug, MAX_UPLOAD_FIELDS
+            );
+
+            let error = Error::StaticMsg("Too many files in one upload").to_sendable();
+            return HttpResponse::PayloadTooLarge().json(error);
+        }
+
+        // Only keep fields which are files, and drop any directories the client sent.
+        // Other fields are still read, and count towards the size limits.
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename().map(String::from))
+            .map(|path| {
+                path.rsplit(|c| c == '/' || c == '\\')
+                    .next()
+                    .map(String::from)
+                    .unwrap_or_default()
+            });
+
+        if let Some(ref filename) = filename {
+            if !is_valid_filename(filename) {
+                return file_error(&slug, io::Error::from(io::ErrorKind::InvalidInput));
+            }
+        }
+
+        // Files are written to disk as they arrive, rather than kept in memory
+        let mut upload = match filename {
+            Some(ref filename) => {
+                let attachments = Attachments::clone(&attachments);
+                let wiki_slug = String::from(wiki_slug(host));
+                let page_slug = slug.clone();
+                let filename = filename.clone();
+                let start = move || attachments.upload(&wiki_slug, &page_slug, &filename);
+
+                Some(try_resp!(blocking(&slug, start).await))
+            }
+            None => None,
+        };
+
+        let mut size = 0;
+
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(error) => {
+                    warn!(
+                        "Failed to read upload {:?} for '{}': {}",
+                        filename, slug, error
+                    );
+
+                    let error = Error::StaticMsg("Invalid file upload").to_sendable();
+                    return HttpResponse::BadRequest().json(error);
+                }
+            };
+
+            size += chunk.len();
+            if size > max_size {
+                debug!(
+                    "Upload {:?} for '{}' exceeds {} bytes",
+                    filename, slug, max_size
+                );
+
+                let error = Error::StaticMsg("File is larger than allowed").to_sendable();
+                let data = FileTooLarge {
+                    filename: filename.unwrap_or_default(),
+                    max_size,
+                };
+
+                return HttpResponse::PayloadTooLarge().json(ErrorData::new(error, data));
+            }
+
+            if let Some(mut current) = upload.take() {
+                upload = Some(try_resp!(
+                    blocking(&slug, move || {
+                        current.write(&chunk)?;
+                        Ok(current)
+                    })
+                    .await
+                ));
+            }
+        }
+
+        let (filename, upload) = match (filename, upload) {
+            (Some(filename), Some(upload)) => (filename, upload),
+            _ => continue,
+        };
+
+        try_resp!(blocking(&slug, move || upload.finish()).await);
+
+        let size = size as u64;
+
+        info!(
+            "Uploaded file '{}' ({} bytes) to page '{}', user ID {}",
+            filename, size, slug, user_id,
+        );
+
+        result.push(FileOutput::new(&slug, filename, size, None));
+    }
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_files_rename(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<FileRenameInput>,
+    deepwell: web::Data<DeepwellPool>,
+    attachments: web::Data<Attachments>,
+    locks: web::Data<EditLocks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/files/rename");
+
+    let host = get_host(&req);
+    let FileRenameInput {
+        slug,
+        filename,
+        new_filename,
+    } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    let user_id =
+        try_resp!(check_files_change(&slug, &id, host, &locks, &settings, &mut deepwell).await);
+
+    let renamed = {
+        let attachments = Attachments::clone(&attachments);
+        let wiki_slug = String::from(wiki_slug(host));
+        let page_slug = slug.clone();
+        let filename = filename.clone();
+        let new_filename = new_filename.clone();
+
+        blocking(&slug, move || {
+            attachments.rename(&wiki_slug, &page_slug, &filename, &new_filename)
+        })
+        .await
+    };
+
+    match renamed {
+        Ok(true) => {
+            info!(
+                "Renamed file '{}' to '{}' on page '{}', user ID {}",
+                filename, new_filename, slug, user_id
+            );
+
+            let result = FileRenameOutput {
+                slug,
+                filename,
+                new_filename,
+            };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Ok(false) => {
+            let error = Error::StaticMsg("File does not exist").to_sendable();
+
+            HttpResponse::NotFound().json(error)
+        }
+        Err(resp) => resp,
+    }
+}
+
+pub async fn api_page_files_delete(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<FileInput>,
+    deepwell: web::Data<DeepwellPool>,
+    attachments: web::Data<Attachments>,
+    locks: web::Data<EditLocks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/files [DELETE]");
+
+    let host = get_host(&req);
+    let FileInput { slug, filename } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    let user_id =
+        try_resp!(check_files_change(&slug, &id, host, &locks, &settings, &mut deepwell).await);
+
+    let deleted = {
+        let attachments = Attachments::clone(&attachments);
+        let wiki_slug = String::from(wiki_slug(host));
+        let page_slug = slug.clone();
+        let filename = filename.clone();
+
+        blocking(&slug, move || {
+            attachments.remove(&wiki_slug, &page_slug, &filename)
+        })
+        .await
+    };
+
+    match deleted {
+        Ok(deleted) => {
+            if deleted {
+                info!(
+                    "Deleted file '{}' from page '{}', user ID {}",
+                    filename, slug, user_id
+                );
+            }
+
+            let result = FileDeleteOutput {
+                slug,
+                filename,
+                deleted,
+            };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(resp) => resp,
+    }
+}
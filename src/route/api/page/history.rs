@@ -0,0 +1,202 @@
+/*
+ * route/api/page/history.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::prelude::*;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+const DEFAULT_HISTORY_LIMIT: u32 = 20;
+const MAX_HISTORY_LIMIT: u32 = 100;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryInput {
+    slug: String,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+/// What parts of a page were changed by a revision.
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChangeFlags {
+    created: bool,
+    source: bool,
+    title: bool,
+    tags: bool,
+    rename: bool,
+}
+
+impl ChangeFlags {
+    pub fn from_revision(revision: &Revision) -> Self {
+        let changes = revision.changes();
+
+        ChangeFlags {
+            created: changes.created,
+            source: changes.source,
+            title: changes.title,
+            tags: changes.tags,
+            rename: changes.rename,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RevisionOutput {
+    revision: u32,
+    revision_id: RevisionId,
+    user_id: UserId,
+    user_name: Option<String>,
+    timestamp: DateTime<Utc>,
+    comment: String,
+    flags: ChangeFlags,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryOutput {
+    slug: String,
+    revisions: Vec<RevisionOutput>,
+    offset: u32,
+    limit: u32,
+    next_offset: Option<u32>,
+}
+
+/// Gets the names of the given users, skipping any which don't exist.
+pub async fn get_user_names(
+    mut user_ids: Vec<UserId>,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<HashMap<UserId, String>, HttpResponse> {
+    user_ids.sort_unstable();
+    user_ids.dedup();
+
+    let result = deepwell.get_users_from_ids(user_ids).await;
+    let users = match result {
+        Ok(Ok(users)) => users,
+        Ok(Err(error)) => return Err(HttpResponse::InternalServerError().json(error)),
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            return Err(HttpResponse::BadGateway().json(error));
+        }
+    };
+
+    let names = users
+        .into_iter()
+        .flatten()
+        .map(|user| (user.id(), String::from(user.name())))
+        .collect();
+
+    Ok(names)
+}
+
+/// Builds revision output objects, looking up the names of the users who made them.
+pub async fn build_revisions(
+    revisions: &[Revision],
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Vec<RevisionOutput>, HttpResponse> {
+    let user_ids = revisions.iter().map(|rev| rev.user_id()).collect();
+    let names = get_user_names(user_ids, deepwell).await?;
+
+    let output = revisions
+        .iter()
+        .map(|revision| RevisionOutput {
+            revision: revision.number(),
+            revision_id: revision.id(),
+            user_id: revision.user_id(),
+            user_name: names.get(&revision.user_id()).cloned(),
+            timestamp: revision.created_at(),
+            comment: String::from(revision.message()),
+            flags: ChangeFlags::from_revision(revision),
+        })
+        .collect();
+
+    Ok(output)
+}
+
+pub async fn api_page_history(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<HistoryInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/history");
+
+    let host = get_host(&req);
+    let HistoryInput {
+        slug,
+        limit,
+        offset,
+    } = arg.into_inner();
+
+    let limit = match limit {
+        Some(0) => {
+            let error = Error::StaticMsg("History limit must be positive").to_sendable();
+
+            return HttpResponse::BadRequest().json(error);
+        }
+        Some(limit) => limit.min(MAX_HISTORY_LIMIT),
+        None => DEFAULT_HISTORY_LIMIT,
+    };
+    let offset = offset.unwrap_or(0);
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id, host, &settings, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    // Get one extra to see if there are more revisions
+    let result = deepwell
+        .get_page_history(wiki_id, slug.clone(), offset, limit + 1)
+        .await;
+
+    let mut revisions = match try_io!(result) {
+        Ok(Some(revisions)) => revisions,
+        Ok(None) => {
+            let error = Error::StaticMsg("Page does not exist").to_sendable();
+
+            return HttpResponse::NotFound().json(error);
+        }
+        Err(error) => {
+            warn!("Failed to get history for page '{}': {}", slug, error);
+
+            return HttpResponse::InternalServerError().json(error);
+        }
+    };
+
+    let next_offset = if revisions.len() > limit as usize {
+        revisions.truncate(limit as usize);
+        offset.checked_add(limit)
+    } else {
+        None
+    };
+
+    let revisions = try_resp!(build_revisions(&revisions, &mut deepwell).await);
+    let result = HistoryOutput {
+        slug,
+        revisions,
+        offset,
+        limit,
+        next_offset,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
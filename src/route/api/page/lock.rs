@@ -0,0 +1,198 @@
+/*
+ * route/api/page/lock.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::prelude::*;
+use crate::lock::{EditLock, EditLocks};
+use deepwell_core::roles::Role;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditLockQuery {
+    slug: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditLockInput {
+    slug: String,
+    duration: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditLockOutput {
+    slug: String,
+    user_id: UserId,
+    expires_at: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditLockStatusOutput {
+    slug: String,
+    locked: bool,
+    user_id: Option<UserId>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditLockReleaseOutput {
+    slug: String,
+    released: bool,
+}
+
+/// Who holds the edit lock on a page, sent as the data of a conflict error.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EditLockConflict {
+    locked_by: UserId,
+    locked_by_name: Option<String>,
+    expires_at: u64,
+}
+
+/// Builds the conflict response for a page locked by someone else.
+pub async fn lock_conflict(
+    slug: &str,
+    lock: EditLock,
+    deepwell: &mut DeepwellClient,
+) -> HttpResponse {
+    debug!("Page '{}' is locked by user ID {}", slug, lock.user_id);
+
+    // The name is only for display, so the conflict is still returned without it
+    let locked_by_name = match deepwell.get_user_from_id(lock.user_id).await {
+        Ok(Ok(Some(user))) => Some(String::from(user.name())),
+        _ => None,
+    };
+
+    let error = Error::StaticMsg("Page is being edited by another user").to_sendable();
+    let data = EditLockConflict {
+        locked_by: lock.user_id,
+        locked_by_name,
+        expires_at: lock.expires_at_unix(),
+    };
+
+    HttpResponse::Conflict().json(ErrorData::new(error, data))
+}
+
+pub async fn api_edit_lock_status(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<EditLockQuery>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/edit-lock [GET]");
+
+    let host = get_host(&req);
+    let EditLockQuery { slug } = arg.into_inner();
+
+    let wiki_id = {
+        let mut deepwell = deepwell.claim().await;
+        try_resp!(check_read(&slug, id, host, &settings, &mut deepwell).await);
+
+        try_resp!(get_wiki_id(host, &mut deepwell).await)
+    };
+
+    let lock = locks.get(wiki_id, &slug);
+    let result = EditLockStatusOutput {
+        slug,
+        locked: lock.is_some(),
+        user_id: lock.map(|lock| lock.user_id),
+        expires_at: lock.map(|lock| lock.expires_at_unix()),
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_edit_lock_acquire(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<EditLockInput>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/edit-lock [POST]");
+
+    let host = get_host(&req);
+    let EditLockInput { slug, duration } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(Role::Member, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    let duration = match duration {
+        Some(0) => {
+            let error = Error::StaticMsg("Lock duration must be positive").to_sendable();
+
+            return HttpResponse::BadRequest().json(error);
+        }
+        Some(seconds) => Duration::from_secs(seconds),
+        None => locks.max_duration(),
+    };
+
+    match locks.acquire(wiki_id, &slug, user_id, duration) {
+        Ok(lock) => {
+            debug!("User ID {} holds edit lock on '{}'", user_id, slug);
+
+            let result = EditLockOutput {
+                slug,
+                user_id,
+                expires_at: lock.expires_at_unix(),
+            };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(lock) => lock_conflict(&slug, lock, &mut deepwell).await,
+    }
+}
+
+pub async fn api_edit_lock_release(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<EditLockQuery>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+) -> HttpResponse {
+    info!("API v0 /page/edit-lock [DELETE]");
+
+    let host = get_host(&req);
+    let EditLockQuery { slug } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    match locks.release(wiki_id, &slug, user_id) {
+        Ok(released) => {
+            debug!("User ID {} released edit lock on '{}'", user_id, slug);
+
+            let result = EditLockReleaseOutput { slug, released };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(lock) => lock_conflict(&slug, lock, &mut deepwell).await,
+    }
+}
@@ -18,6 +18,32 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use super::prelude::*;
+use super::prelude;
 
-// TODO
+mod delete;
+mod edit;
+mod files;
+mod history;
+mod lock;
+mod parent;
+mod preview;
+mod query;
+mod rename;
+mod revision;
+mod source;
+mod tags;
+mod vote;
+
+pub use self::delete::*;
+pub use self::edit::*;
+pub use self::files::*;
+pub use self::history::*;
+pub use self::lock::*;
+pub use self::parent::*;
+pub use self::preview::*;
+pub use self::query::*;
+pub use self::rename::*;
+pub use self::revision::*;
+pub use self::source::*;
+pub use self::tags::*;
+pub use self::vote::*;
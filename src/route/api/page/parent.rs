@@ -0,0 +1,195 @@
+/*
+ * route/api/page/parent.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::edit::{page_commit, EDIT_ROLE};
+use super::prelude::*;
+use crate::cache::Ancestor;
+use crate::hooks::{PageChange, PageHooks};
+use crate::route::parent::{creates_cycle, get_ancestors, get_parent, readable_ancestors};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ParentInput {
+    slug: String,
+}
+
+/// Changes the parent of a page. If `parent` is `None`, the page's parent is removed.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ParentSetInput {
+    slug: String,
+    parent: Option<String>,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChildOutput {
+    slug: String,
+    title: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ParentOutput {
+    slug: String,
+    parent: Option<String>,
+    ancestors: Vec<Ancestor>,
+    children: Vec<ChildOutput>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ParentSetOutput {
+    slug: String,
+    parent: Option<String>,
+    revision_id: RevisionId,
+}
+
+/// The parent which would make a page its own ancestor, sent as the data of an error.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ParentCycle {
+    parent: String,
+}
+
+pub async fn api_page_parent_get(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<ParentInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/parent [GET]");
+
+    let host = get_host(&req);
+    let ParentInput { slug } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let role = try_resp!(get_role(id, host, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    // Pages the user can't read are left out, so their titles aren't shown
+    let readable = |slug: &str| read_role(&settings, slug) <= role;
+
+    let parent = try_resp!(get_parent(wiki_id, &slug, &mut deepwell).await)
+        .filter(|parent| readable(parent));
+    let ancestors = try_resp!(get_ancestors(wiki_id, &slug, &mut deepwell).await);
+    let ancestors = readable_ancestors(&ancestors, role, &settings);
+
+    let result = deepwell.get_page_children(wiki_id, slug.clone()).await;
+    let mut children: Vec<ChildOutput> = match try_io!(result) {
+        Ok(pages) => pages
+            .iter()
+            .filter(|page| readable(page.slug()))
+            .map(|page| ChildOutput {
+                slug: String::from(page.slug()),
+                title: String::from(page.title()),
+            })
+            .collect(),
+        Err(error) => {
+            warn!("Failed to get children of page '{}': {}", slug, error);
+
+            return HttpResponse::InternalServerError().json(error);
+        }
+    };
+
+    children.sort_by(|a, b| a.slug.cmp(&b.slug));
+
+    let result = ParentOutput {
+        slug,
+        parent,
+        ancestors,
+        children,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_parent_set(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<ParentSetInput>,
+    deepwell: web::Data<DeepwellPool>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/parent [POST]");
+
+    let host = get_host(&req);
+    let ParentSetInput {
+        slug,
+        parent,
+        comment,
+    } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(EDIT_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    if let Some(ref parent) = parent {
+        try_resp!(check_read(parent, id.clone(), host, &settings, &mut deepwell).await);
+        try_resp!(get_existing_page(wiki_id, parent, &mut deepwell).await);
+
+        if try_resp!(creates_cycle(wiki_id, &slug, parent, &mut deepwell).await) {
+            debug!("Refusing to make '{}' the parent of '{}'", parent, slug);
+
+            let error = Error::StaticMsg("Page cannot be its own ancestor").to_sendable();
+            let data = ParentCycle {
+                parent: parent.clone(),
+            };
+
+            return HttpResponse::BadRequest().json(ErrorData::new(error, data));
+        }
+    }
+
+    let commit = page_commit(wiki_id, &slug, &comment, user_id);
+    let result = deepwell.set_page_parent(commit, parent.clone()).await;
+
+    match try_io!(result) {
+        Ok(revision_id) => {
+            info!(
+                "Set parent of page '{}' to {:?}, user ID {}",
+                slug, parent, user_id
+            );
+
+            hooks.page_changed(wiki_id, host, &slug, PageChange::Reparented);
+
+            let result = ParentSetOutput {
+                slug,
+                parent,
+                revision_id,
+            };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(error) => {
+            warn!("Failed to set parent of page '{}': {}", slug, error);
+
+            HttpResponse::InternalServerError().json(error)
+        }
+    }
+}
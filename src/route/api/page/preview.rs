@@ -0,0 +1,108 @@
+/*
+ * route/api/page/preview.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::edit::EDIT_ROLE;
+use super::prelude::*;
+use crate::route::render::render_wikitext;
+use crate::tags::normalize_tag;
+use ftml_rpc::{HtmlOutput, PageInfoOwned, ParseWarning};
+
+/// Wikitext to render, along with the page it would be saved to.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PreviewInput {
+    slug: String,
+    title: Option<String>,
+    alt_title: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    wikitext: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PreviewOutput {
+    slug: String,
+    html: String,
+    style: String,
+    warnings: Vec<ParseWarning>,
+}
+
+/// Renders wikitext for the editor's preview, without saving anything.
+///
+/// Only users who could save the page may preview it. Apart from that check,
+/// this does not contact DEEPWELL, so the page need not exist, and has its own,
+/// stricter ratelimit since each call is a full render.
+/// For the same reason, `[[include]]` blocks are not expanded.
+pub async fn api_page_preview(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PreviewInput>,
+    deepwell: web::Data<DeepwellPool>,
+    ftml: web::Data<FtmlPool>,
+) -> HttpResponse {
+    info!("API v0 /page/preview");
+
+    let host = get_host(&req);
+
+    let PreviewInput {
+        slug,
+        title,
+        alt_title,
+        tags,
+        wikitext,
+    } = arg.into_inner();
+
+    {
+        let mut deepwell = deepwell.claim().await;
+        try_resp!(check_role(EDIT_ROLE, id, host, &mut deepwell).await);
+    }
+
+    let page_info = PageInfoOwned {
+        title: title.unwrap_or_else(|| slug.clone()),
+        alt_title,
+        header: None,
+        subheader: None,
+        rating: 0.0,
+        tags: tags.iter().map(|tag| normalize_tag(tag)).collect(),
+    };
+
+    let HtmlOutput {
+        html,
+        style,
+        warnings,
+        ..
+    } = try_resp!(render_wikitext(&ftml, page_info, wikitext).await);
+
+    debug!(
+        "Rendered preview for '{}' ({} bytes of HTML)",
+        slug,
+        html.len(),
+    );
+
+    let result = PreviewOutput {
+        slug,
+        html,
+        style,
+        warnings,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
@@ -0,0 +1,345 @@
+/*
+ * route/api/page/query.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Querying for pages, similar to Wikidot's `ListPages` module.
+
+use super::prelude::*;
+use crate::page_list::PageListCache;
+use crate::rating::{Rating, ScoreCache};
+use crate::tags::TagQuery;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+
+const DEFAULT_QUERY_LIMIT: u32 = 20;
+const MAX_QUERY_LIMIT: u32 = 250;
+
+/// How many pages' votes are fetched from DEEPWELL at once.
+const SCORE_FETCH_CONCURRENCY: usize = 4;
+
+/// The category of pages without one, as in Wikidot.
+const DEFAULT_CATEGORY: &str = "_default";
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueryOrder {
+    Name,
+    Title,
+    CreatedAt,
+    Rating,
+}
+
+impl Default for QueryOrder {
+    #[inline]
+    fn default() -> Self {
+        QueryOrder::Name
+    }
+}
+
+/// Filters and ordering for a page query. All filters are optional.
+///
+/// `tags` uses `ListPages` syntax, see `TagQuery`, with `+` written as `%2B`.
+/// Required tags can also be listed in `required-tags`.
+/// `category` may be `_default` for pages without a category.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueryInput {
+    category: Option<String>,
+    tags: Option<String>,
+    required_tags: Option<String>,
+    created_by: Option<String>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    min_rating: Option<i32>,
+    max_rating: Option<i32>,
+    parent: Option<String>,
+    #[serde(default)]
+    order: QueryOrder,
+    #[serde(default)]
+    descending: bool,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageSummary {
+    slug: String,
+    category: String,
+    title: String,
+    alt_title: Option<String>,
+    tags: Vec<String>,
+    created_at: DateTime<Utc>,
+    created_by: UserId,
+    rating: i32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueryOutput {
+    pages: Vec<PageSummary>,
+    total: usize,
+    next_offset: Option<u32>,
+}
+
+/// Gets the category of a page from its slug.
+pub fn page_category(slug: &str) -> &str {
+    match slug.find(':') {
+        Some(index) => &slug[..index],
+        None => DEFAULT_CATEGORY,
+    }
+}
+
+/// Gets the score of a page from its votes.
+async fn get_score(
+    wiki_id: WikiId,
+    slug: &str,
+    deepwell: &DeepwellPool,
+) -> StdResult<(String, i32), HttpResponse> {
+    let votes = get_votes(wiki_id, slug, &mut deepwell.claim().await).await?;
+    let rating = Rating::from_votes(&votes);
+
+    Ok((String::from(slug), rating.score))
+}
+
+/// Gets the scores of the given pages, by slug.
+///
+/// Scores are cached, and any which aren't are fetched concurrently.
+async fn get_scores(
+    wiki_id: WikiId,
+    pages: &[&Page],
+    scores: &mut HashMap<String, i32>,
+    score_cache: &ScoreCache,
+    deepwell: &DeepwellPool,
+) -> StdResult<(), HttpResponse> {
+    let mut needed: Vec<&str> = pages
+        .iter()
+        .map(|page| page.slug())
+        .filter(|slug| !scores.contains_key(*slug))
+        .collect();
+
+    let generation = score_cache.get(wiki_id, needed.iter().copied(), scores);
+    needed.retain(|slug| !scores.contains_key(*slug));
+
+    if needed.is_empty() {
+        return Ok(());
+    }
+
+    debug!("Fetching scores of {} pages", needed.len());
+
+    let fetched: Vec<_> = stream::iter(needed)
+        .map(|slug| get_score(wiki_id, slug, deepwell))
+        .buffer_unordered(SCORE_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    let fetched = fetched.into_iter().collect::<StdResult<Vec<_>, _>>()?;
+    score_cache.insert(wiki_id, generation, &fetched);
+    scores.extend(fetched);
+
+    Ok(())
+}
+
+pub async fn api_page_query(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<QueryInput>,
+    deepwell_pool: web::Data<DeepwellPool>,
+    page_lists: web::Data<PageListCache>,
+    score_cache: web::Data<ScoreCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/query");
+
+    let host = get_host(&req);
+    let QueryInput {
+        category,
+        tags,
+        required_tags,
+        created_by,
+        created_after,
+        created_before,
+        min_rating,
+        max_rating,
+        parent,
+        order,
+        descending,
+        limit,
+        offset,
+    } = arg.into_inner();
+
+    let limit = match limit {
+        Some(0) => {
+            let error = Error::StaticMsg("Query limit must be positive").to_sendable();
+
+            return HttpResponse::BadRequest().json(error);
+        }
+        Some(limit) => limit.min(MAX_QUERY_LIMIT),
+        None => DEFAULT_QUERY_LIMIT,
+    };
+    let offset = offset.unwrap_or(0);
+    let tags = TagQuery::from_params(tags.as_deref(), required_tags.as_deref());
+
+    let mut deepwell = deepwell_pool.claim().await;
+    let role = try_resp!(get_role(id, host, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    let empty = || {
+        let result = QueryOutput {
+            pages: Vec::new(),
+            total: 0,
+            next_offset: None,
+        };
+
+        HttpResponse::Ok().json(Success::from(result))
+    };
+
+    // Resolve filters which need DEEPWELL
+    let author_id = match created_by {
+        Some(name) => match try_io!(deepwell.get_user_from_name(name.clone()).await) {
+            Ok(Some(user)) => Some(user.id()),
+            Ok(None) => {
+                debug!("No user '{}' for page query, no results", name);
+
+                return empty();
+            }
+            Err(error) => return HttpResponse::InternalServerError().json(error),
+        },
+        None => None,
+    };
+
+    let children: Option<HashSet<String>> = match parent {
+        Some(parent) => match try_io!(deepwell.get_page_children(wiki_id, parent).await) {
+            Ok(pages) => Some(pages.iter().map(|page| String::from(page.slug())).collect()),
+            Err(error) => return HttpResponse::InternalServerError().json(error),
+        },
+        None => None,
+    };
+
+    // Filter pages
+    let pages = try_resp!(get_cached_pages(wiki_id, &page_lists, &mut deepwell).await);
+    let mut pages: Vec<&Page> = pages
+        .iter()
+        .filter(|page| read_role(&settings, page.slug()) <= role)
+        .filter(|page| match category {
+            Some(ref category) => page_category(page.slug()).eq_ignore_ascii_case(category),
+            None => true,
+        })
+        .filter(|page| match tags {
+            Some(ref tags) => tags.matches(page.tags()),
+            None => true,
+        })
+        .filter(|page| match author_id {
+            Some(author_id) => page.created_by() == author_id,
+            None => true,
+        })
+        .filter(|page| match created_after {
+            Some(date) => page.created_at() >= date,
+            None => true,
+        })
+        .filter(|page| match created_before {
+            Some(date) => page.created_at() < date,
+            None => true,
+        })
+        .filter(|page| match children {
+            Some(ref children) => children.contains(page.slug()),
+            None => true,
+        })
+        .collect();
+
+    // Scores are fetched using other clients from the pool
+    drop(deepwell);
+
+    // Ratings are fetched per page, so only get them for every page if needed
+    let mut scores = HashMap::new();
+
+    if min_rating.is_some() || max_rating.is_some() || order == QueryOrder::Rating {
+        try_resp!(get_scores(wiki_id, &pages, &mut scores, &score_cache, &deepwell_pool).await);
+
+        pages.retain(|page| {
+            let score = scores[page.slug()];
+
+            min_rating.map_or(true, |min| score >= min)
+                && max_rating.map_or(true, |max| score <= max)
+        });
+    }
+
+    // Order and paginate
+    match order {
+        QueryOrder::Name => pages.sort_by(|a, b| a.slug().cmp(b.slug())),
+        QueryOrder::Title => pages.sort_by(|a, b| {
+            let a_title = a.title().to_lowercase();
+            let b_title = b.title().to_lowercase();
+
+            a_title.cmp(&b_title).then_with(|| a.slug().cmp(b.slug()))
+        }),
+        QueryOrder::CreatedAt => pages.sort_by(|a, b| {
+            a.created_at()
+                .cmp(&b.created_at())
+                .then_with(|| a.slug().cmp(b.slug()))
+        }),
+        QueryOrder::Rating => pages.sort_by(|a, b| {
+            scores[a.slug()]
+                .cmp(&scores[b.slug()])
+                .then_with(|| a.slug().cmp(b.slug()))
+        }),
+    }
+
+    if descending {
+        pages.reverse();
+    }
+
+    let total = pages.len();
+    let pages: Vec<&Page> = pages
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+
+    try_resp!(get_scores(wiki_id, &pages, &mut scores, &score_cache, &deepwell_pool).await);
+
+    let next_offset = if (offset as usize) + pages.len() < total {
+        Some(offset + pages.len() as u32)
+    } else {
+        None
+    };
+
+    let pages = pages
+        .iter()
+        .map(|page| PageSummary {
+            slug: String::from(page.slug()),
+            category: String::from(page_category(page.slug())),
+            title: String::from(page.title()),
+            alt_title: page.alt_title().map(String::from),
+            tags: page.tags().to_vec(),
+            created_at: page.created_at(),
+            created_by: page.created_by(),
+            rating: scores[page.slug()],
+        })
+        .collect();
+
+    let result = QueryOutput {
+        pages,
+        total,
+        next_offset,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
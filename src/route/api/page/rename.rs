@@ -0,0 +1,198 @@
+/*
+ * route/api/page/rename.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::edit::{page_commit, EDIT_ROLE};
+use super::lock::lock_conflict;
+use super::prelude::*;
+use crate::hooks::{PageChange, PageHooks};
+use crate::lock::EditLocks;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageRenameInput {
+    slug: String,
+    new_slug: String,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageRenameOutput {
+    old_slug: String,
+    new_slug: String,
+    revision_id: RevisionId,
+}
+
+/// Removes the redirect from a slug, since a page now exists there.
+/// Failures are logged but otherwise ignored, as the page itself takes priority.
+pub async fn clear_redirect(wiki_id: WikiId, slug: &str, deepwell: &mut DeepwellClient) {
+    match deepwell.remove_page_redirect(wiki_id, slug.into()).await {
+        Ok(Ok(true)) => debug!("Removed redirect from '{}', page now exists", slug),
+        Ok(Ok(false)) => (),
+        Ok(Err(error)) => warn!("Failed to remove redirect from '{}': {}", slug, error),
+        Err(error) => warn!(
+            "Unable to reach DEEPWELL to remove redirect '{}': {}",
+            slug, error
+        ),
+    }
+}
+
+/// Moves a page to a new slug, which must not already have a page.
+/// Any redirect from the new slug is removed, since the page now claims it.
+pub async fn move_page(
+    wiki_id: WikiId,
+    slug: &str,
+    new_slug: &str,
+    comment: &str,
+    user_id: UserId,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<RevisionId, HttpResponse> {
+    get_existing_page(wiki_id, slug, deepwell).await?;
+
+    // Check that the destination is free
+    match deepwell.get_page(wiki_id, new_slug.into()).await {
+        Ok(Ok(None)) => (),
+        Ok(Ok(Some(_))) => {
+            debug!(
+                "Cannot move '{}' to '{}', it already exists",
+                slug, new_slug
+            );
+
+            let error = Error::StaticMsg("Page already exists").to_sendable();
+            return Err(HttpResponse::Conflict().json(error));
+        }
+        Ok(Err(error)) => return Err(HttpResponse::InternalServerError().json(error)),
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            return Err(HttpResponse::BadGateway().json(error));
+        }
+    }
+
+    // Move page
+    let commit = page_commit(wiki_id, slug, comment, user_id);
+
+    let revision_id = match deepwell.rename_page(commit, new_slug.into()).await {
+        Ok(Ok(revision_id)) => revision_id,
+        Ok(Err(error)) => {
+            warn!(
+                "Failed to move page '{}' to '{}': {}",
+                slug, new_slug, error
+            );
+
+            return Err(HttpResponse::InternalServerError().json(error));
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            return Err(HttpResponse::BadGateway().json(error));
+        }
+    };
+
+    clear_redirect(wiki_id, new_slug, deepwell).await;
+
+    Ok(revision_id)
+}
+
+pub async fn api_page_rename(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<PageRenameInput>,
+    deepwell: web::Data<DeepwellPool>,
+    locks: web::Data<EditLocks>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/rename");
+
+    let host = get_host(&req);
+    let PageRenameInput {
+        slug,
+        new_slug,
+        comment,
+    } = arg.into_inner();
+
+    let slug = normalize_slug(&slug);
+    let new_slug = normalize_slug(&new_slug);
+    if new_slug.is_empty() || new_slug == slug {
+        let error = Error::StaticMsg("Invalid new page name").to_sendable();
+
+        return HttpResponse::BadRequest().json(error);
+    }
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(EDIT_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    try_resp!(check_read(&new_slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    // Don't move a page someone else is editing
+    if let Some(lock) = locks.get(wiki_id, &slug) {
+        if lock.user_id != user_id {
+            return lock_conflict(&slug, lock, &mut deepwell).await;
+        }
+    }
+
+    let revision_id =
+        try_resp!(move_page(wiki_id, &slug, &new_slug, &comment, user_id, &mut deepwell).await);
+
+    info!(
+        "Renamed page '{}' to '{}' (revision {}), user ID {}",
+        slug, new_slug, revision_id, user_id
+    );
+
+    let _ = locks.release(wiki_id, &slug, user_id);
+    hooks.page_changed(
+        wiki_id,
+        host,
+        &slug,
+        PageChange::Moved {
+            new_slug: &new_slug,
+        },
+    );
+
+    // Point the old slug at the new one.
+    // The page has already moved, so the rename still succeeds without it.
+    let result = deepwell
+        .add_page_redirect(wiki_id, slug.clone(), new_slug.clone())
+        .await;
+
+    match result {
+        Ok(Ok(_)) => (),
+        Ok(Err(error)) => warn!(
+            "Failed to add redirect from '{}' to '{}': {}",
+            slug, new_slug, error
+        ),
+        Err(error) => warn!(
+            "Failed to reach DEEPWELL to add redirect from '{}' to '{}': {}",
+            slug, new_slug, error
+        ),
+    }
+
+    let result = PageRenameOutput {
+        old_slug: slug,
+        new_slug,
+        revision_id,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
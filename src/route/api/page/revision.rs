@@ -0,0 +1,202 @@
+/*
+ * route/api/page/revision.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::prelude::*;
+use crate::cache::RenderCache;
+use crate::diff::{diff, diff_html, DiffChunk, DiffMode};
+use crate::route::render::{self, render_page_cached, RenderContext};
+use chrono::{DateTime, Utc};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RevisionInput {
+    slug: String,
+    revision: u32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PageRevisionOutput {
+    slug: String,
+    revision: u32,
+    revision_id: RevisionId,
+    user_id: UserId,
+    timestamp: DateTime<Utc>,
+    comment: String,
+    wikitext: String,
+    html: String,
+    style: String,
+    missing_includes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct DiffInput {
+    slug: String,
+    from: u32,
+    to: u32,
+    #[serde(default)]
+    mode: DiffMode,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct DiffOutput {
+    slug: String,
+    from: u32,
+    to: u32,
+    mode: DiffMode,
+    chunks: Vec<DiffChunk>,
+    html: String,
+}
+
+/// Fetches a revision of a page, returning `404` if it doesn't exist.
+pub async fn get_revision(
+    deepwell: &mut DeepwellClient,
+    wiki_id: WikiId,
+    slug: &str,
+    number: u32,
+) -> StdResult<(Revision, String), HttpResponse> {
+    let result = deepwell
+        .get_page_revision(wiki_id, String::from(slug), number)
+        .await;
+
+    match result {
+        Ok(Ok(Some(revision))) => Ok(revision),
+        Ok(Ok(None)) => {
+            let error = Error::StaticMsg("Page revision does not exist").to_sendable();
+
+            Err(HttpResponse::NotFound().json(error))
+        }
+        Ok(Err(error)) => {
+            warn!("Failed to get revision {} of '{}': {}", number, slug, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+pub async fn api_page_revision(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<RevisionInput>,
+    deepwell: web::Data<DeepwellPool>,
+    ftml: web::Data<FtmlPool>,
+    cache: web::Data<RenderCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/revision");
+
+    let host = get_host(&req);
+    let RevisionInput { slug, revision } = arg.into_inner();
+
+    // Release the DEEPWELL client before rendering
+    let (wiki_id, revision_info, wikitext) = {
+        let mut deepwell = deepwell.claim().await;
+        try_resp!(check_read(&slug, id, host, &settings, &mut deepwell).await);
+        let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+        let (revision_info, wikitext) =
+            try_resp!(get_revision(&mut deepwell, wiki_id, &slug, revision).await);
+
+        (wiki_id, revision_info, wikitext)
+    };
+
+    let ctx = RenderContext {
+        deepwell: &deepwell,
+        ftml: &ftml,
+        cache: &cache,
+        settings: &settings,
+        wiki_id,
+    };
+
+    // Render with the title and tags as of this revision, not the current ones
+    let render = try_resp!(
+        render_page_cached(
+            &ctx,
+            &slug,
+            revision_info.id(),
+            render::revision_info(&revision_info),
+            wikitext.clone(),
+        )
+        .await
+    );
+
+    let result = PageRevisionOutput {
+        slug,
+        revision,
+        revision_id: revision_info.id(),
+        user_id: revision_info.user_id(),
+        timestamp: revision_info.created_at(),
+        comment: String::from(revision_info.message()),
+        wikitext,
+        html: render.html.clone(),
+        style: render.style.clone(),
+        missing_includes: render.missing_includes.clone(),
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_diff(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<DiffInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/diff");
+
+    let host = get_host(&req);
+    let DiffInput {
+        slug,
+        from,
+        to,
+        mode,
+    } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id, host, &settings, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    let (_, old) = try_resp!(get_revision(&mut deepwell, wiki_id, &slug, from).await);
+    let (_, new) = try_resp!(get_revision(&mut deepwell, wiki_id, &slug, to).await);
+
+    debug!("Comparing revisions {} and {} of '{}'", from, to, slug);
+
+    let chunks = diff(&old, &new, mode);
+    let html = diff_html(&chunks);
+
+    let result = DiffOutput {
+        slug,
+        from,
+        to,
+        mode,
+        chunks,
+        html,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
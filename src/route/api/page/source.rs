@@ -0,0 +1,116 @@
+/*
+ * route/api/page/source.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::prelude::*;
+use super::revision::get_revision;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceInput {
+    slug: String,
+    revision: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceOutput {
+    slug: String,
+    revision: Option<u32>,
+    wikitext: String,
+}
+
+/// Gets the wikitext of the page, or the requested revision of it.
+async fn get_source(
+    req: &HttpRequest,
+    id: Identity,
+    arg: &SourceInput,
+    deepwell: &DeepwellPool,
+    settings: &RuntimeSettings,
+) -> StdResult<String, HttpResponse> {
+    let host = get_host(req);
+    let SourceInput { slug, revision } = arg;
+
+    let mut deepwell = deepwell.claim().await;
+    check_read(slug, id, host, settings, &mut deepwell).await?;
+    let wiki_id = get_wiki_id(host, &mut deepwell).await?;
+
+    if let Some(revision) = revision {
+        let (_, wikitext) = get_revision(&mut deepwell, wiki_id, slug, *revision).await?;
+
+        return Ok(wikitext);
+    }
+
+    match deepwell.get_page(wiki_id, slug.clone()).await {
+        Ok(Ok(Some((_, wikitext)))) => Ok(wikitext),
+        Ok(Ok(None)) => {
+            let error = Error::StaticMsg("Page does not exist").to_sendable();
+
+            Err(HttpResponse::NotFound().json(error))
+        }
+        Ok(Err(error)) => {
+            warn!("Failed to get source for '{}': {}", slug, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+pub async fn api_page_source(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<SourceInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/source");
+
+    let wikitext = try_resp!(get_source(&req, id, &arg, &deepwell, &settings).await);
+    let SourceInput { slug, revision } = arg.into_inner();
+
+    let result = SourceOutput {
+        slug,
+        revision,
+        wikitext,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+/// Returns the wikitext as plain text, for use in scripts.
+pub async fn api_page_source_raw(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<SourceInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/source/raw");
+
+    let wikitext = try_resp!(get_source(&req, id, &arg, &deepwell, &settings).await);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(wikitext)
+}
@@ -0,0 +1,215 @@
+/*
+ * route/api/page/tags.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::edit::{page_commit, EDIT_ROLE};
+use super::prelude::*;
+use crate::hooks::{PageChange, PageHooks};
+use crate::page_list::PageListCache;
+use crate::tags::{count_tags, is_valid_tag, normalize_tag};
+use std::collections::BTreeSet;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagsInput {
+    slug: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagsOutput {
+    slug: String,
+    tags: Vec<String>,
+}
+
+/// Changes to a page's tags.
+///
+/// If `tags` is present, it replaces all the page's tags.
+/// Then any tags in `add` are added, and any in `remove` are removed.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagsEditInput {
+    slug: String,
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagsEditOutput {
+    slug: String,
+    tags: Vec<String>,
+    revision_id: RevisionId,
+}
+
+/// The tag which doesn't follow the tag rules, sent as the data of an error.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct InvalidTag {
+    tag: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+pub async fn api_page_tags_get(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<TagsInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/tags [GET]");
+
+    let host = get_host(&req);
+    let TagsInput { slug } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id, host, &settings, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    let page = try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    let result = TagsOutput {
+        slug,
+        tags: page.tags().to_vec(),
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_tags_edit(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<TagsEditInput>,
+    deepwell: web::Data<DeepwellPool>,
+    hooks: web::Data<PageHooks>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/tags [POST]");
+
+    let host = get_host(&req);
+    let TagsEditInput {
+        slug,
+        tags,
+        add,
+        remove,
+        comment,
+    } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_role(EDIT_ROLE, id.clone(), host, &mut deepwell).await);
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    let page = try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    // Build new tag set
+    let mut new_tags: BTreeSet<String> = match tags {
+        Some(tags) => tags.iter().map(|tag| normalize_tag(tag)).collect(),
+        None => page.tags().iter().cloned().collect(),
+    };
+
+    for tag in &add {
+        new_tags.insert(normalize_tag(tag));
+    }
+
+    for tag in &remove {
+        new_tags.remove(&normalize_tag(tag));
+    }
+
+    if let Some(tag) = new_tags.iter().find(|tag| !is_valid_tag(tag)) {
+        debug!("Invalid tag '{}' for page '{}'", tag, slug);
+
+        let error = Error::StaticMsg("Tag is not valid").to_sendable();
+        let data = InvalidTag { tag: tag.clone() };
+
+        return HttpResponse::BadRequest().json(ErrorData::new(error, data));
+    }
+
+    let tags: Vec<String> = new_tags.into_iter().collect();
+
+    // Save tags
+    let commit = page_commit(wiki_id, &slug, &comment, user_id);
+    let result = deepwell.tag_page(commit, tags.clone()).await;
+
+    match try_io!(result) {
+        Ok(revision_id) => {
+            info!(
+                "Set tags on page '{}' to {:?}, user ID {}",
+                slug, tags, user_id
+            );
+
+            hooks.page_changed(wiki_id, host, &slug, PageChange::Tagged { tags: &tags });
+
+            let result = TagsEditOutput {
+                slug,
+                tags,
+                revision_id,
+            };
+
+            HttpResponse::Ok().json(Success::from(result))
+        }
+        Err(error) => {
+            warn!("Failed to set tags on page '{}': {}", slug, error);
+
+            HttpResponse::InternalServerError().json(error)
+        }
+    }
+}
+
+/// Lists all the tags used on pages this user can read, with how many pages have each.
+pub async fn api_tags_all(
+    req: HttpRequest,
+    id: Identity,
+    deepwell: web::Data<DeepwellPool>,
+    page_lists: web::Data<PageListCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /tags");
+
+    let host = get_host(&req);
+
+    let mut deepwell = deepwell.claim().await;
+    let role = try_resp!(get_role(id, host, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    let pages = try_resp!(get_cached_pages(wiki_id, &page_lists, &mut deepwell).await);
+
+    let readable = pages
+        .iter()
+        .filter(|page| read_role(&settings, page.slug()) <= role);
+
+    let result: Vec<TagCount> = count_tags(readable)
+        .into_iter()
+        .map(|(tag, count)| TagCount {
+            tag: String::from(tag),
+            count,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(Success::from(result))
+}
@@ -0,0 +1,248 @@
+/*
+ * route/api/page/vote.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::history::get_user_names;
+use super::prelude::*;
+use crate::rating::{Rating, ScoreCache, VoteValue};
+use deepwell_core::roles::Role;
+use std::convert::TryFrom;
+
+/// Role needed to vote on pages.
+const VOTE_ROLE: Role = Role::Member;
+
+/// Role needed to see who voted on a page, and how.
+const VOTERS_ROLE: Role = Role::Moderator;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoteQuery {
+    slug: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoteStatusInput {
+    slug: String,
+    #[serde(default)]
+    voters: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoteInput {
+    slug: String,
+    vote: VoteValue,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoterOutput {
+    user_id: UserId,
+    user_name: Option<String>,
+    vote: VoteValue,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoteStatusOutput {
+    slug: String,
+    rating: Rating,
+    votes: u32,
+    own_vote: Option<VoteValue>,
+    voters: Option<Vec<VoterOutput>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoteOutput {
+    slug: String,
+    rating: Rating,
+    votes: u32,
+    vote: Option<VoteValue>,
+}
+
+/// Finds the vote made by the given user, if any.
+fn user_vote(votes: &[(UserId, i8)], user_id: UserId) -> Option<VoteValue> {
+    votes
+        .iter()
+        .find(|(id, _)| *id == user_id)
+        .and_then(|&(_, value)| VoteValue::try_from(value).ok())
+}
+
+pub async fn api_page_vote_status(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<VoteStatusInput>,
+    deepwell: web::Data<DeepwellPool>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/vote [GET]");
+
+    let host = get_host(&req);
+    let VoteStatusInput { slug, voters } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+
+    if voters {
+        try_resp!(check_role(VOTERS_ROLE, id.clone(), host, &mut deepwell).await);
+    }
+
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+    let votes = try_resp!(get_votes(wiki_id, &slug, &mut deepwell).await);
+    let rating = Rating::from_votes(&votes);
+
+    let own_vote = match id.identity() {
+        Some(_) => {
+            let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+
+            user_vote(&votes, user_id)
+        }
+        None => None,
+    };
+
+    let voters = if voters {
+        let user_ids = votes.iter().map(|&(user_id, _)| user_id).collect();
+        let names = try_resp!(get_user_names(user_ids, &mut deepwell).await);
+
+        let voters = votes
+            .iter()
+            .filter_map(|&(user_id, value)| {
+                let vote = VoteValue::try_from(value).ok()?;
+
+                Some(VoterOutput {
+                    user_id,
+                    user_name: names.get(&user_id).cloned(),
+                    vote,
+                })
+            })
+            .collect();
+
+        Some(voters)
+    } else {
+        None
+    };
+
+    let result = VoteStatusOutput {
+        slug,
+        rating,
+        votes: rating.votes(),
+        own_vote,
+        voters,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_vote_cast(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<VoteInput>,
+    deepwell: web::Data<DeepwellPool>,
+    scores: web::Data<ScoreCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/vote [POST]");
+
+    let host = get_host(&req);
+    let VoteInput { slug, vote } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    try_resp!(check_role(VOTE_ROLE, id.clone(), host, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+    try_resp!(get_existing_page(wiki_id, &slug, &mut deepwell).await);
+
+    let result = deepwell
+        .set_page_vote(wiki_id, slug.clone(), user_id, vote.value())
+        .await;
+
+    if let Err(error) = try_io!(result) {
+        warn!("Failed to record vote on page '{}': {}", slug, error);
+
+        return HttpResponse::InternalServerError().json(error);
+    }
+
+    debug!("User ID {} voted {:?} on page '{}'", user_id, vote, slug);
+
+    let votes = try_resp!(get_votes(wiki_id, &slug, &mut deepwell).await);
+    let rating = Rating::from_votes(&votes);
+    scores.set(wiki_id, &slug, rating.score);
+
+    let result = VoteOutput {
+        slug,
+        rating,
+        votes: rating.votes(),
+        vote: Some(vote),
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
+
+pub async fn api_page_vote_remove(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Json<VoteQuery>,
+    deepwell: web::Data<DeepwellPool>,
+    scores: web::Data<ScoreCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /page/vote [DELETE]");
+
+    let host = get_host(&req);
+    let VoteQuery { slug } = arg.into_inner();
+
+    let mut deepwell = deepwell.claim().await;
+    try_resp!(check_read(&slug, id.clone(), host, &settings, &mut deepwell).await);
+    let user_id = try_resp!(get_user_id(&id, &mut deepwell).await);
+    let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+    let result = deepwell
+        .remove_page_vote(wiki_id, slug.clone(), user_id)
+        .await;
+
+    match try_io!(result) {
+        Ok(removed) => {
+            if removed {
+                debug!("User ID {} removed their vote on page '{}'", user_id, slug);
+            }
+        }
+        Err(error) => {
+            warn!("Failed to remove vote on page '{}': {}", slug, error);
+
+            return HttpResponse::InternalServerError().json(error);
+        }
+    }
+
+    let votes = try_resp!(get_votes(wiki_id, &slug, &mut deepwell).await);
+    let rating = Rating::from_votes(&votes);
+    scores.set(wiki_id, &slug, rating.score);
+
+    let result = VoteOutput {
+        slug,
+        rating,
+        votes: rating.votes(),
+        vote: None,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
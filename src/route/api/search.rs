@@ -0,0 +1,105 @@
+/*
+ * route/api/search.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::prelude::*;
+use crate::search::{SearchFilter, SearchIndex, SearchResult};
+use crate::tags::TagQuery;
+
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchInput {
+    q: String,
+    category: Option<String>,
+    tags: Option<String>,
+    required_tags: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchOutput {
+    results: Vec<SearchResult>,
+    total: usize,
+    next_offset: Option<usize>,
+}
+
+pub async fn api_search(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<SearchInput>,
+    deepwell: web::Data<DeepwellPool>,
+    search: web::Data<SearchIndex>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("API v0 /search");
+
+    let host = get_host(&req);
+    let SearchInput {
+        q,
+        category,
+        tags,
+        required_tags,
+        limit,
+        offset,
+    } = arg.into_inner();
+
+    let limit = limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .max(1)
+        .min(MAX_SEARCH_LIMIT);
+    let offset = offset.unwrap_or(0);
+
+    let (role, wiki_id) = {
+        let mut deepwell = deepwell.claim().await;
+        let role = try_resp!(get_role(id, host, &mut deepwell).await);
+        let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+        (role, wiki_id)
+    };
+
+    try_resp!(load_search_index(&search, wiki_id, &deepwell).await);
+
+    let tags = TagQuery::from_params(tags.as_deref(), required_tags.as_deref());
+    let filter = SearchFilter {
+        category: category.as_ref().map(|category| category.as_str()),
+        tags: tags.as_ref(),
+    };
+
+    let allowed = |slug: &str| read_role(&settings, slug) <= role;
+    let (total, results) = search.search(wiki_id, &q, &filter, allowed, offset, limit);
+
+    let next_offset = if offset + results.len() < total {
+        Some(offset + results.len())
+    } else {
+        None
+    };
+
+    let result = SearchOutput {
+        results,
+        total,
+        next_offset,
+    };
+
+    HttpResponse::Ok().json(Success::from(result))
+}
@@ -38,8 +38,10 @@
 //! ```
 //!
 //! This is done by the `deepwell_core::SendableError` object.
+//! Errors with more details for the client also have a `data` field, see `ErrorData`.
 
-use serde::Serialize;
+use deepwell_core::SendableError;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::Debug;
 
 #[derive(Debug, Serialize)]
@@ -60,3 +62,43 @@ where
         Success { result }
     }
 }
+
+/// An error with extra details for the client, such as who holds a lock:
+/// ```text
+/// {
+///     "error": "...",
+///     "message": "...",
+///     "data": { ... }
+/// }
+/// ```
+#[derive(Debug, Serialize)]
+pub struct ErrorData<T>
+where
+    T: Debug + Serialize,
+{
+    #[serde(flatten)]
+    error: SendableError,
+    data: T,
+}
+
+impl<T> ErrorData<T>
+where
+    T: Debug + Serialize,
+{
+    #[inline]
+    pub fn new(error: SendableError, data: T) -> Self {
+        ErrorData { error, data }
+    }
+}
+
+/// Deserializes an optional field which can also be explicitly cleared.
+///
+/// Used with `#[serde(default, deserialize_with = "nullable")]`, an absent
+/// field is `None`, while `null` is `Some(None)`.
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,277 @@
+/*
+ * route/changes.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Site-wide recent changes, similar to Wikidot's `system:recent-changes`.
+
+use super::api::{get_user_names, page_category, DELETED_CATEGORY};
+use super::prelude::*;
+use chrono::{DateTime, Utc};
+use deepwell_core::error::Error;
+use deepwell_core::roles::Role;
+use deepwell_core::types::{Revision, RevisionId, UserId, WikiId};
+use deepwell_rpc::Client as DeepwellClient;
+
+/// How many revisions to fetch from DEEPWELL at once while filtering.
+const RECENT_CHANGES_BATCH: u32 = 100;
+
+/// How many batches to look through for matching changes before giving up.
+///
+/// This bounds the work done for filters which match very little,
+/// the returned offset lets the caller continue from where this stopped.
+const MAX_RECENT_CHANGES_BATCHES: u32 = 10;
+
+/// The kinds of change a revision can make to a page.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeType {
+    Created,
+    Edited,
+    Renamed,
+    Tags,
+    Deleted,
+}
+
+impl ChangeType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "created" => Some(ChangeType::Created),
+            "edited" => Some(ChangeType::Edited),
+            "renamed" => Some(ChangeType::Renamed),
+            "tags" => Some(ChangeType::Tags),
+            "deleted" => Some(ChangeType::Deleted),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeType::Created => "created",
+            ChangeType::Edited => "edited",
+            ChangeType::Renamed => "renamed",
+            ChangeType::Tags => "tags",
+            ChangeType::Deleted => "deleted",
+        }
+    }
+
+    /// Gets the kinds of change made by a revision, which left the page at `slug`.
+    ///
+    /// Deleting a page moves it into the deleted category,
+    /// so such renames are reported as deletions instead.
+    fn from_revision(slug: &str, revision: &Revision) -> Vec<Self> {
+        let changes = revision.changes();
+        let mut types = Vec::new();
+
+        if changes.created {
+            types.push(ChangeType::Created);
+        }
+
+        if !changes.created && (changes.source || changes.title) {
+            types.push(ChangeType::Edited);
+        }
+
+        if changes.rename {
+            if page_category(slug) == DELETED_CATEGORY {
+                types.push(ChangeType::Deleted);
+            } else {
+                types.push(ChangeType::Renamed);
+            }
+        }
+
+        if changes.tags {
+            types.push(ChangeType::Tags);
+        }
+
+        types
+    }
+}
+
+/// Filters for recent changes. All filters are optional.
+///
+/// `category` may be `_default` for pages without a category.
+#[derive(Debug)]
+pub struct ChangeFilter {
+    pub category: Option<String>,
+    pub user: Option<String>,
+    pub change_type: Option<ChangeType>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecentChange {
+    pub slug: String,
+    pub revision: u32,
+    pub revision_id: RevisionId,
+    pub user_id: UserId,
+    pub user_name: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub comment: String,
+    pub changes: Vec<ChangeType>,
+}
+
+/// A page of recent changes, newest first.
+///
+/// Offsets are positions in the site's history rather than in the filtered results,
+/// so `next_offset` should be passed back as-is to get the following page.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecentChanges {
+    pub changes: Vec<RecentChange>,
+    pub offset: u32,
+    pub next_offset: Option<u32>,
+}
+
+/// Gets up to `limit` recent changes matching `filter`, which the given role can see.
+pub async fn get_recent_changes(
+    wiki_id: WikiId,
+    role: Role,
+    filter: &ChangeFilter,
+    offset: u32,
+    limit: u32,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<RecentChanges, HttpResponse> {
+    debug!(
+        "Getting recent changes for wiki ID {} from {}",
+        wiki_id, offset
+    );
+
+    let empty = || RecentChanges {
+        changes: Vec::new(),
+        offset,
+        next_offset: None,
+    };
+
+    let user_id = match filter.user {
+        Some(ref name) => match deepwell.get_user_from_name(name.clone()).await {
+            Ok(Ok(Some(user))) => Some(user.id()),
+            Ok(Ok(None)) => {
+                debug!("No user '{}' for recent changes, no results", name);
+
+                return Ok(empty());
+            }
+            Ok(Err(error)) => return Err(HttpResponse::InternalServerError().json(error)),
+            Err(error) => {
+                let error = Error::ServiceTransport(error).to_sendable();
+
+                return Err(HttpResponse::BadGateway().json(error));
+            }
+        },
+        None => None,
+    };
+
+    let mut matched = Vec::new();
+    let mut position = offset;
+    let mut next_offset = None;
+
+    'batches: for _ in 0..MAX_RECENT_CHANGES_BATCHES {
+        let result = deepwell
+            .get_recent_revisions(wiki_id, position, RECENT_CHANGES_BATCH)
+            .await;
+
+        let revisions = match result {
+            Ok(Ok(revisions)) => revisions,
+            Ok(Err(error)) => {
+                warn!(
+                    "Failed to get recent changes for wiki ID {}: {}",
+                    wiki_id, error
+                );
+
+                return Err(HttpResponse::InternalServerError().json(error));
+            }
+            Err(error) => {
+                let error = Error::ServiceTransport(error).to_sendable();
+
+                return Err(HttpResponse::BadGateway().json(error));
+            }
+        };
+
+        let exhausted = revisions.len() < RECENT_CHANGES_BATCH as usize;
+
+        for (slug, revision) in revisions {
+            if matched.len() >= limit as usize {
+                next_offset = Some(position);
+                break 'batches;
+            }
+
+            position += 1;
+
+            if read_role(settings, &slug) > role {
+                continue;
+            }
+
+            if let Some(ref category) = filter.category {
+                if !page_category(&slug).eq_ignore_ascii_case(category) {
+                    continue;
+                }
+            }
+
+            if let Some(user_id) = user_id {
+                if revision.user_id() != user_id {
+                    continue;
+                }
+            }
+
+            let changes = ChangeType::from_revision(&slug, &revision);
+
+            if let Some(change_type) = filter.change_type {
+                if !changes.contains(&change_type) {
+                    continue;
+                }
+            }
+
+            matched.push((slug, revision, changes));
+        }
+
+        if exhausted {
+            next_offset = None;
+            break;
+        }
+
+        // Stopped looking, but there may be more changes further back
+        next_offset = Some(position);
+    }
+
+    // Look up who made the changes
+    let user_ids = matched
+        .iter()
+        .map(|(_, revision, _)| revision.user_id())
+        .collect();
+    let names = get_user_names(user_ids, deepwell).await?;
+
+    let changes = matched
+        .into_iter()
+        .map(|(slug, revision, changes)| RecentChange {
+            slug,
+            revision: revision.number(),
+            revision_id: revision.id(),
+            user_id: revision.user_id(),
+            user_name: names.get(&revision.user_id()).cloned(),
+            timestamp: revision.created_at(),
+            comment: String::from(revision.message()),
+            changes,
+        })
+        .collect();
+
+    Ok(RecentChanges {
+        changes,
+        offset,
+        next_offset,
+    })
+}
@@ -0,0 +1,299 @@
+/*
+ * route/feed.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Routes for RSS and Atom feeds of site activity.
+//!
+//! Feeds of new forum threads in a category and new posts in a thread are
+//! not served yet, since the forum itself isn't implemented. They are left
+//! for a follow-up request, at `/feed/forum/ct-{category}.{ext}` and
+//! `/feed/forum/t-{thread}.{ext}`.
+
+use super::api::get_user_names;
+use super::changes::{get_recent_changes, ChangeFilter};
+use super::prelude::*;
+use crate::feed::{
+    http_date, not_modified, Feed, FeedCache, FeedEntry, FeedFormat, FeedKind, RenderedFeed,
+};
+use crate::remote::DeepwellPool;
+use deepwell_core::roles::Role;
+use deepwell_core::types::{Page, WikiId};
+use deepwell_rpc::Client as DeepwellClient;
+use std::sync::Arc;
+
+/// How many entries are in each feed.
+const FEED_ENTRIES: usize = 20;
+
+/// How long feed readers may use a feed without checking it again, in seconds.
+const FEED_MAX_AGE: u32 = 300;
+
+fn feed_format(extension: &str) -> StdResult<FeedFormat, HttpResponse> {
+    FeedFormat::from_extension(extension).ok_or_else(|| {
+        debug!("Unknown feed format '{}'", extension);
+
+        HttpResponse::NotFound().finish()
+    })
+}
+
+/// Serves a feed, or `304 Not Modified` if the client already has it.
+fn feed_response(req: &HttpRequest, feed: &RenderedFeed, format: FeedFormat) -> HttpResponse {
+    let header = |name: http::header::HeaderName| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    let cache_control = format!("public, max-age={}", FEED_MAX_AGE);
+    let fresh = not_modified(
+        header(http::header::IF_NONE_MATCH),
+        header(http::header::IF_MODIFIED_SINCE),
+        &feed.etag,
+        feed.updated,
+    );
+
+    let mut response = if fresh {
+        debug!("Feed not modified, ETag {}", feed.etag);
+
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+
+    response
+        .header(http::header::ETAG, feed.etag.as_str())
+        .header(http::header::LAST_MODIFIED, http_date(feed.updated))
+        .header(http::header::CACHE_CONTROL, cache_control);
+
+    if fresh {
+        response.finish()
+    } else {
+        response
+            .content_type(format.content_type())
+            .body(feed.body.clone())
+    }
+}
+
+/// Builds the feed of newly created pages.
+async fn pages_feed(
+    wiki_id: WikiId,
+    base: &str,
+    site_name: &str,
+    self_link: String,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Feed, HttpResponse> {
+    let pages = get_pages(wiki_id, deepwell).await?;
+
+    // Feeds are public, so only include pages anyone can read
+    let mut pages: Vec<&Page> = pages
+        .iter()
+        .filter(|page| read_role(settings, page.slug()) == Role::Guest)
+        .collect();
+
+    pages.sort_by(|a, b| b.created_at().cmp(&a.created_at()));
+    pages.truncate(FEED_ENTRIES);
+
+    let user_ids = pages.iter().map(|page| page.created_by()).collect();
+    let names = get_user_names(user_ids, deepwell).await?;
+
+    let entries = pages
+        .iter()
+        .map(|page| {
+            let link = format!("{}/{}", base, encode_path_segment(page.slug()));
+
+            FeedEntry {
+                id: format!("{}#page-{}", link, page.id()),
+                title: String::from(page.title()),
+                link,
+                updated: page.created_at(),
+                author: names.get(&page.created_by()).cloned(),
+                summary: format!("New page '{}'", page.slug()),
+            }
+        })
+        .collect();
+
+    Ok(Feed {
+        title: format!("New pages on {}", site_name),
+        link: format!("{}/", base),
+        self_link,
+        description: String::from("Pages recently created on this site"),
+        entries,
+    })
+}
+
+/// Builds the feed of recent changes to pages.
+async fn site_changes_feed(
+    wiki_id: WikiId,
+    base: &str,
+    site_name: &str,
+    self_link: String,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Feed, HttpResponse> {
+    let filter = ChangeFilter {
+        category: None,
+        user: None,
+        change_type: None,
+    };
+
+    let result = get_recent_changes(
+        wiki_id,
+        Role::Guest,
+        &filter,
+        0,
+        FEED_ENTRIES as u32,
+        settings,
+        deepwell,
+    )
+    .await?;
+
+    let entries = result
+        .changes
+        .into_iter()
+        .map(|change| {
+            let link = format!("{}/{}", base, encode_path_segment(&change.slug));
+            let types: Vec<&str> = change.changes.iter().map(|change| change.name()).collect();
+
+            FeedEntry {
+                id: format!("{}#revision-{}", link, change.revision_id),
+                title: format!("{} ({})", change.slug, types.join(", ")),
+                link,
+                updated: change.timestamp,
+                author: change.user_name,
+                summary: change.comment,
+            }
+        })
+        .collect();
+
+    Ok(Feed {
+        title: format!("Recent changes on {}", site_name),
+        link: format!("{}/system:recent-changes", base),
+        self_link,
+        description: String::from("Recent changes to pages on this site"),
+        entries,
+    })
+}
+
+/// Gets a feed, building it if it isn't cached.
+///
+/// Links use the wiki's configured URL, not the request's host.
+async fn get_feed(
+    host: Option<&str>,
+    kind: FeedKind,
+    format: FeedFormat,
+    feeds: &FeedCache,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Arc<RenderedFeed>, HttpResponse> {
+    let wiki_id = get_wiki_id(host, deepwell).await?;
+
+    if let Ok(feed) = feeds.get(wiki_id, kind, format) {
+        return Ok(feed);
+    }
+
+    // Only one request builds a wiki's feeds, any others wait for it
+    let loading = feeds.load_lock(wiki_id);
+    let _guard = loading.lock().await;
+
+    let generation = match feeds.get(wiki_id, kind, format) {
+        Ok(feed) => return Ok(feed),
+        Err(generation) => generation,
+    };
+
+    debug!("Building {:?} feed for wiki ID {}", kind, wiki_id);
+
+    let wiki_slug = get_wiki_slug(host);
+    let base = settings.site_url(wiki_slug);
+    let site_name = settings.site_name(wiki_slug);
+
+    let feed = match kind {
+        FeedKind::Pages => {
+            let self_link = format!("{}/feed/pages.{}", base, format.extension());
+
+            pages_feed(wiki_id, &base, site_name, self_link, settings, deepwell).await?
+        }
+        FeedKind::SiteChanges => {
+            let self_link = format!("{}/feed/site-changes.{}", base, format.extension());
+
+            site_changes_feed(wiki_id, &base, site_name, self_link, settings, deepwell).await?
+        }
+    };
+
+    let feed = RenderedFeed::new(&feed, format);
+
+    Ok(feeds.insert(wiki_id, kind, format, generation, feed))
+}
+
+/// Feed of newly created pages.
+pub async fn feed_pages(
+    req: HttpRequest,
+    extension: web::Path<String>,
+    deepwell: web::Data<DeepwellPool>,
+    feeds: web::Data<FeedCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    let host = get_host(&req);
+
+    info!("GET feed pages [{}]", host.unwrap_or("none"));
+
+    let format = try_resp!(feed_format(&extension));
+    let mut deepwell = deepwell.claim().await;
+    let feed = try_resp!(
+        get_feed(
+            host,
+            FeedKind::Pages,
+            format,
+            &feeds,
+            &settings,
+            &mut deepwell
+        )
+        .await
+    );
+
+    feed_response(&req, &feed, format)
+}
+
+/// Feed of recent changes to pages.
+pub async fn feed_site_changes(
+    req: HttpRequest,
+    extension: web::Path<String>,
+    deepwell: web::Data<DeepwellPool>,
+    feeds: web::Data<FeedCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    let host = get_host(&req);
+
+    info!("GET feed site-changes [{}]", host.unwrap_or("none"));
+
+    let format = try_resp!(feed_format(&extension));
+    let mut deepwell = deepwell.claim().await;
+    let feed = try_resp!(
+        get_feed(
+            host,
+            FeedKind::SiteChanges,
+            format,
+            &feeds,
+            &settings,
+            &mut deepwell
+        )
+        .await
+    );
+
+    feed_response(&req, &feed, format)
+}
@@ -19,8 +19,11 @@
  */
 
 use super::prelude::*;
-use crate::config::RuntimeSettings;
+use crate::attachment::Attachments;
+use crate::remote::DeepwellPool;
 use actix_files::NamedFile;
+use actix_identity::Identity;
+use actix_web::http::header::DispositionType;
 
 pub async fn static_file(req: HttpRequest, settings: web::Data<RuntimeSettings>) -> HttpResult {
     let info = req.match_info();
@@ -33,3 +36,96 @@ pub async fn static_file(req: HttpRequest, settings: web::Data<RuntimeSettings>)
     let resp = file.into_response(&req)?;
     Ok(resp)
 }
+
+/// Whether browsers can show a file of this type without running anything in it.
+///
+/// Other types, such as HTML or SVG, could run scripts on the wiki's origin,
+/// so they are only served as downloads.
+fn is_passive_type(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+
+    match essence {
+        "image/svg+xml" => false,
+        "text/plain" => true,
+        _ => {
+            essence.starts_with("image/")
+                || essence.starts_with("audio/")
+                || essence.starts_with("video/")
+        }
+    }
+}
+
+/// Serves a file attached to a page, as `/local--files/{page}/{filename}`.
+pub async fn local_file(
+    req: HttpRequest,
+    id: Identity,
+    parts: web::Path<(String, String)>,
+    deepwell: web::Data<DeepwellPool>,
+    attachments: web::Data<Attachments>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResult {
+    let host = get_host(&req);
+    let (page, filename) = parts.into_inner();
+
+    info!(
+        "GET file {}/{} [{}]",
+        page,
+        filename,
+        host.unwrap_or("none"),
+    );
+
+    let mut deepwell = deepwell.claim().await;
+    if let Err(resp) = check_read(&page, id, host, &settings, &mut deepwell).await {
+        return Ok(resp);
+    }
+
+    let wiki_slug = match get_wiki_slug(host) {
+        Some(slug) => slug,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let path = match attachments.get(wiki_slug, &page, &filename) {
+        Some(path) => path,
+        None => {
+            debug!("No file '{}' attached to page '{}'", filename, page);
+
+            return Ok(HttpResponse::NotFound().finish());
+        }
+    };
+
+    let file = NamedFile::open(&path)?;
+    let file = if is_passive_type(file.content_type().as_ref()) {
+        file
+    } else {
+        debug!("Serving file '{}' as a download", filename);
+
+        let mut disposition = file.content_disposition().clone();
+        disposition.disposition = DispositionType::Attachment;
+        file.set_content_disposition(disposition)
+    };
+
+    let mut resp = file.into_response(&req)?;
+    resp.headers_mut().insert(
+        http::header::X_CONTENT_TYPE_OPTIONS,
+        http::HeaderValue::from_static("nosniff"),
+    );
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passive_types() {
+        assert!(is_passive_type("image/png"));
+        assert!(is_passive_type("video/webm"));
+        assert!(is_passive_type("text/plain; charset=utf-8"));
+        assert!(!is_passive_type("image/svg+xml"));
+        assert!(!is_passive_type("text/html"));
+        assert!(!is_passive_type("application/xhtml+xml"));
+        assert!(!is_passive_type("application/javascript"));
+        assert!(!is_passive_type("application/octet-stream"));
+    }
+}
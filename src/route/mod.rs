@@ -19,7 +19,10 @@
  */
 
 mod prelude {
+    pub use super::pages::*;
     pub use super::permissions::*;
+    pub use super::wiki::*;
+    pub use crate::config::RuntimeSettings;
     pub use crate::utils::*;
     pub use crate::StdResult;
     pub use actix_web::Error as ActixError;
@@ -29,20 +32,32 @@ mod prelude {
     pub type HttpResult = StdResult<HttpResponse, ActixError>;
 }
 
+#[macro_use]
+mod macros;
+
 mod account;
 mod api;
+mod changes;
+mod feed;
 mod files;
 mod forum;
 mod page;
+mod pages;
+mod parent;
 mod permissions;
+mod render;
+mod sitemap;
 mod temp;
 mod user;
+mod wiki;
 
 pub use self::account::*;
 pub use self::api::*;
+pub use self::feed::*;
 pub use self::files::*;
 pub use self::forum::*;
 pub use self::page::*;
 pub use self::permissions::*;
+pub use self::sitemap::*;
 pub use self::temp::*;
 pub use self::user::*;
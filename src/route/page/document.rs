@@ -0,0 +1,264 @@
+/*
+ * route/page/document.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Builders for the HTML page contents served for each page mode.
+//!
+//! These are inserted into the site layout, see `crate::template`.
+
+use crate::cache::Ancestor;
+use crate::rating::Rating;
+use crate::utils::escape_html;
+use actix_web::http;
+use deepwell_core::types::RevisionId;
+
+/// Wraps the given response with the HTML content type.
+macro_rules! html_response {
+    ($resp:expr, $body:expr) => {
+        $resp.content_type("text/html; charset=utf-8").body($body)
+    };
+}
+
+/// How many characters of the page to use as its description in link previews.
+const DESCRIPTION_LENGTH: usize = 200;
+
+/// Everything about a page which goes into the site layout.
+#[derive(Debug)]
+pub struct PageContents {
+    pub status: http::StatusCode,
+    pub title: String,
+    pub style: String,
+    pub rating: String,
+    pub content: String,
+
+    /// Plain text summary of the page, unescaped.
+    pub description: String,
+
+    /// Whether search engines may index this page.
+    pub indexable: bool,
+}
+
+impl PageContents {
+    pub fn new<S: Into<String>>(title: S, content: String) -> Self {
+        PageContents {
+            status: http::StatusCode::OK,
+            title: title.into(),
+            style: String::new(),
+            rating: String::new(),
+            content,
+            description: String::new(),
+            indexable: true,
+        }
+    }
+}
+
+/// Builds the metadata in the document head, such as the canonical URL and
+/// OpenGraph and Twitter card tags for link previews.
+pub fn head_body(contents: &PageContents, canonical_url: &str, site_name: &str) -> String {
+    let title = escape_html(&contents.title);
+    let canonical_url = escape_html(canonical_url);
+    let description = escape_html(&truncate_description(&contents.description));
+
+    let mut html = format!(
+        "<link rel=\"canonical\" href=\"{}\">\n\
+         <meta property=\"og:type\" content=\"website\">\n\
+         <meta property=\"og:url\" content=\"{}\">\n\
+         <meta property=\"og:title\" content=\"{}\">\n\
+         <meta property=\"og:site_name\" content=\"{}\">\n\
+         <meta name=\"twitter:card\" content=\"summary\">\n\
+         <meta name=\"twitter:title\" content=\"{}\">\n",
+        canonical_url,
+        canonical_url,
+        title,
+        escape_html(site_name),
+        title,
+    );
+
+    if !description.is_empty() {
+        html.push_str(&format!(
+            "<meta name=\"description\" content=\"{}\">\n\
+             <meta property=\"og:description\" content=\"{}\">\n\
+             <meta name=\"twitter:description\" content=\"{}\">\n",
+            description, description, description,
+        ));
+    }
+
+    if !contents.indexable {
+        html.push_str("<meta name=\"robots\" content=\"noindex\">\n");
+    }
+
+    html
+}
+
+/// Shortens a description to about `DESCRIPTION_LENGTH` characters, at a word boundary.
+fn truncate_description(description: &str) -> String {
+    if description.chars().count() <= DESCRIPTION_LENGTH {
+        return String::from(description);
+    }
+
+    let mut truncated = String::new();
+
+    for word in description.split_whitespace() {
+        if truncated.chars().count() + word.chars().count() + 1 > DESCRIPTION_LENGTH {
+            break;
+        }
+
+        if !truncated.is_empty() {
+            truncated.push(' ');
+        }
+
+        truncated.push_str(word);
+    }
+
+    // Words which are too long on their own are cut off
+    if truncated.is_empty() {
+        truncated = description.chars().take(DESCRIPTION_LENGTH).collect();
+    }
+
+    truncated.push('…');
+    truncated
+}
+
+/// Builds the contents shown when a page does not exist.
+pub fn missing_page_body(slug: &str) -> String {
+    format!(
+        "<p>The page <em>{}</em> you want to access does not exist.</p>\n\
+         <p><a href=\"/{}/edit/true\">Create page</a></p>",
+        escape_html(slug),
+        escape_html(slug),
+    )
+}
+
+/// Builds the notice for a page the user isn't allowed to see.
+pub fn forbidden_page_body(slug: &str) -> String {
+    format!(
+        "<p>You do not have permission to view the page <em>{}</em>.</p>",
+        escape_html(slug),
+    )
+}
+
+/// Builds the notice for a page requested at an offset.
+///
+/// Only system pages which list things, such as recent changes, can be paged through.
+pub fn offset_body(slug: &str, offset: u32) -> String {
+    format!(
+        "<p>The page <em>{}</em> cannot be shown at offset {}.</p>\n\
+         <p><a href=\"/{}\">View page</a></p>",
+        escape_html(slug),
+        offset,
+        escape_html(slug),
+    )
+}
+
+/// Builds the rating module shown alongside a page.
+/// Voting itself is done client-side through the `page/vote` API.
+pub fn rating_body(slug: &str, rating: &Rating) -> String {
+    format!(
+        "<div class=\"page-rate-widget-box\" data-slug=\"{}\" data-votes=\"{}\">\n\
+         <span class=\"rate-points\">rating: <span class=\"number\">{}</span></span>\n\
+         <span class=\"rateup btn\"><a href=\"javascript:;\">+</a></span>\n\
+         <span class=\"ratedown btn\"><a href=\"javascript:;\">&ndash;</a></span>\n\
+         <span class=\"cancel btn\"><a href=\"javascript:;\">x</a></span>\n\
+         </div>",
+        escape_html(slug),
+        rating.votes(),
+        rating.display(),
+    )
+}
+
+/// Builds the breadcrumb trail shown above a page with parents.
+/// Returns an empty string if the page has no parents.
+pub fn breadcrumbs_body(ancestors: &[Ancestor], title: &str) -> String {
+    if ancestors.is_empty() {
+        return String::new();
+    }
+
+    let mut html = String::from("<div id=\"breadcrumbs\">");
+
+    for ancestor in ancestors {
+        html.push_str(&format!(
+            "<a href=\"/{}\">{}</a> &raquo; ",
+            escape_html(&ancestor.slug),
+            escape_html(&ancestor.title),
+        ));
+    }
+
+    html.push_str(&escape_html(title));
+    html.push_str("</div>\n");
+    html
+}
+
+/// Builds the contents showing a page's wikitext.
+pub fn source_body(wikitext: &str) -> String {
+    format!(
+        "<div class=\"page-source\">\n<pre>{}</pre>\n</div>",
+        escape_html(wikitext),
+    )
+}
+
+/// Builds the page editor.
+/// If `existing` is `None`, then this page is being created,
+/// otherwise it has the current wikitext and the revision editing starts from.
+pub fn edit_body(slug: &str, title: &str, existing: Option<(&str, RevisionId)>) -> String {
+    let (wikitext, base_revision) = match existing {
+        Some((wikitext, revision_id)) => (wikitext, revision_id.to_string()),
+        None => ("", String::new()),
+    };
+
+    format!(
+        "<form id=\"page-edit\" data-slug=\"{}\" data-new=\"{}\" data-base-revision=\"{}\">\n\
+         <input type=\"text\" name=\"title\" value=\"{}\">\n\
+         <textarea name=\"wikitext\">{}</textarea>\n\
+         <input type=\"text\" name=\"comment\">\n\
+         </form>",
+        escape_html(slug),
+        existing.is_none(),
+        base_revision,
+        escape_html(title),
+        escape_html(wikitext),
+    )
+}
+
+/// Builds the container for a page's revision history.
+pub fn history_body(slug: &str) -> String {
+    format!(
+        "<div id=\"page-history\" data-slug=\"{}\"></div>",
+        escape_html(slug),
+    )
+}
+
+/// Builds the contents for a particular revision of a page.
+/// If `html` is `None`, then that revision does not exist.
+pub fn revision_body(slug: &str, revision: u32, html: Option<&str>) -> String {
+    match html {
+        Some(html) => format!(
+            "<div class=\"revision-notice\">This is revision {} of <a href=\"/{}\">{}</a>.</div>\n{}",
+            revision,
+            escape_html(slug),
+            escape_html(slug),
+            html,
+        ),
+        None => format!(
+            "<p>Revision {} of <a href=\"/{}\">{}</a> does not exist.</p>",
+            revision,
+            escape_html(slug),
+            escape_html(slug),
+        ),
+    }
+}
@@ -0,0 +1,524 @@
+/*
+ * route/page/mod.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#[macro_use]
+mod document;
+
+mod mode;
+mod search;
+mod system;
+
+use self::document::*;
+use self::mode::PageMode;
+use self::system::{is_system_page, system_page};
+use super::parent::readable_ancestors;
+use super::prelude::*;
+use super::render::*;
+use crate::cache::{CachedRender, RenderCache};
+use crate::page_list::PageListCache;
+use crate::rating::Rating;
+use crate::remote::{DeepwellPool, FtmlPool};
+use crate::template::{LayoutContext, Templates};
+use actix_identity::Identity;
+use deepwell_core::error::Error;
+use deepwell_core::roles::Role;
+use deepwell_core::types::Page;
+use std::sync::Arc;
+use wikidot_path::{redirect, ArgumentValue, Request as PageRequest};
+
+pub use self::search::search_page;
+
+/// The shared services used to serve pages, besides DEEPWELL and the settings.
+#[derive(Debug, Clone)]
+pub struct PageServices {
+    pub ftml: FtmlPool,
+    pub templates: Templates,
+    pub cache: RenderCache,
+    pub page_lists: PageListCache,
+}
+
+// Public route methods
+
+/// Route handling for pages, with arguments or not.
+pub async fn page_get(
+    req: HttpRequest,
+    id: Identity,
+    deepwell: web::Data<DeepwellPool>,
+    services: web::Data<PageServices>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    let host = get_host(&req);
+    let path = req.uri().path();
+
+    info!("GET page {} [{}]", path, host.unwrap_or("none"));
+
+    let page_req = PageRequest::parse(path);
+
+    // Editing or printing a page still refers to the page itself
+    let canonical_path = match PageMode::from_arguments(&page_req.arguments) {
+        PageMode::Edit | PageMode::Print => format!("/{}", get_page_slug(&page_req)),
+        _ => String::from(path),
+    };
+
+    let canonical_url = canonical_url(&req, &canonical_path, &settings);
+
+    render_page(
+        host,
+        id,
+        &page_req,
+        &canonical_url,
+        &settings,
+        &deepwell,
+        &services,
+    )
+    .await
+}
+
+/// Route for root, which is the same as whatever the `main` page is.
+pub async fn page_main(
+    req: HttpRequest,
+    id: Identity,
+    settings: web::Data<RuntimeSettings>,
+    deepwell: web::Data<DeepwellPool>,
+    services: web::Data<PageServices>,
+) -> HttpResponse {
+    let host = get_host(&req);
+
+    info!("GET / [{}]", host.unwrap_or("none"));
+
+    let path = format!("/{}", settings.main_page(get_wiki_slug(host)));
+    let page_req = PageRequest::parse(&path);
+    let canonical_url = canonical_url(&req, "/", &settings);
+
+    render_page(
+        host,
+        id,
+        &page_req,
+        &canonical_url,
+        &settings,
+        &deepwell,
+        &services,
+    )
+    .await
+}
+
+/// Route for Wikidot's printer-friendly pages, such as `/printer--friendly/scp-173`.
+///
+/// This is the same as the page's `print/true` mode.
+pub async fn page_printer_friendly(
+    req: HttpRequest,
+    id: Identity,
+    deepwell: web::Data<DeepwellPool>,
+    services: web::Data<PageServices>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    let host = get_host(&req);
+    let page = req.match_info().query("page");
+
+    info!("GET printer-friendly {} [{}]", page, host.unwrap_or("none"));
+
+    // Not normalized by middleware, since the prefix isn't in normal form
+    let path = format!("/{}", page.trim_matches('/'));
+    let path = redirect(&path).unwrap_or(path);
+    let path = format!("{}/print/true", path);
+
+    let page_req = PageRequest::parse(&path);
+    let canonical_url = canonical_url(&req, &format!("/{}", get_page_slug(&page_req)), &settings);
+
+    render_page(
+        host,
+        id,
+        &page_req,
+        &canonical_url,
+        &settings,
+        &deepwell,
+        &services,
+    )
+    .await
+}
+
+// Helper functions
+
+/// Fetches the given page from DEEPWELL and serves it according to the requested mode.
+async fn render_page(
+    host: Option<&str>,
+    id: Identity,
+    page_req: &PageRequest<'_>,
+    canonical_url: &str,
+    settings: &RuntimeSettings,
+    deepwell: &DeepwellPool,
+    services: &PageServices,
+) -> HttpResponse {
+    let slug = get_page_slug(page_req);
+    let mode = PageMode::from_arguments(&page_req.arguments);
+
+    debug!("Serving page '{}' with mode {:?}", slug, mode);
+
+    // Only hold a DEEPWELL client while looking up the page, not while rendering it
+    let (wiki_id, prepared, page) = {
+        let mut deepwell = deepwell.claim().await;
+        let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+        if !try_resp!(can_read(&slug, id.clone(), host, settings, &mut deepwell).await) {
+            debug!("Not allowed to read page '{}'", slug);
+
+            let mut contents = PageContents::new(&slug, forbidden_page_body(&slug));
+            contents.status = http::StatusCode::FORBIDDEN;
+            (wiki_id, Some(contents), None)
+        } else if is_system_page(&slug) {
+            let role = try_resp!(get_role(id, host, &mut deepwell).await);
+            let result = system_page(
+                &slug,
+                page_req,
+                role,
+                settings,
+                &services.page_lists,
+                &mut deepwell,
+                wiki_id,
+            )
+            .await;
+
+            (wiki_id, Some(try_resp!(result)), None)
+        } else {
+            let result = deepwell.get_page(wiki_id, slug.clone()).await;
+            let page = match try_io!(result) {
+                Ok(page) => page,
+                Err(error) => {
+                    warn!("Failed to retrieve page '{}': {}", slug, error);
+
+                    return HttpResponse::InternalServerError().json(error);
+                }
+            };
+
+            // Follow redirects left by renamed pages, unless creating a page here
+            if page.is_none() && mode != PageMode::Edit {
+                let result = get_redirect(wiki_id, &slug, &mut deepwell).await;
+
+                if let Some(target) = try_resp!(result) {
+                    let path = redirect_path(&target, page_req);
+
+                    info!("REDIRECT {} -> {}", slug, path);
+
+                    return HttpResponse::Found()
+                        .header(http::header::LOCATION, path)
+                        .finish();
+                }
+            }
+
+            (wiki_id, None, page)
+        }
+    };
+
+    let ctx = RenderContext {
+        deepwell,
+        ftml: &services.ftml,
+        cache: &services.cache,
+        settings,
+        wiki_id,
+    };
+
+    // Build page contents, unless this is a system page or can't be read
+    let mut contents = match prepared {
+        Some(contents) => contents,
+        None => try_resp!(page_contents(&slug, mode, page, &ctx).await),
+    };
+
+    // Editors and old revisions shouldn't show up in search engines
+    contents.indexable = mode.is_indexable();
+
+    match mode {
+        PageMode::Print => {
+            print_response(host, contents, canonical_url, settings, &services.templates)
+        }
+        PageMode::Bare => bare_response(contents),
+        _ => layout_response(host, contents, canonical_url, &ctx, &services.templates).await,
+    }
+}
+
+/// Wraps page contents in the site layout, with the site's navigation.
+async fn layout_response(
+    host: Option<&str>,
+    contents: PageContents,
+    canonical_url: &str,
+    ctx: &RenderContext<'_>,
+    templates: &Templates,
+) -> HttpResponse {
+    let head = head_body(
+        &contents,
+        canonical_url,
+        ctx.settings.site_name(get_wiki_slug(host)),
+    );
+
+    let PageContents {
+        status,
+        title,
+        mut style,
+        rating,
+        content,
+        ..
+    } = contents;
+
+    // Render navigation and wrap in site layout
+    let nav_top = render_nav(ctx, "nav:top").await;
+    let nav_side = render_nav(ctx, "nav:side").await;
+
+    let mut nav = |output: Option<Arc<CachedRender>>| match output {
+        Some(output) => {
+            style.push_str(&output.style);
+            output.html.clone()
+        }
+        None => String::new(),
+    };
+
+    let nav_top = nav(nav_top);
+    let nav_side = nav(nav_side);
+
+    let context = LayoutContext {
+        title: &title,
+        head: &head,
+        style: &style,
+        nav_top: &nav_top,
+        nav_side: &nav_side,
+        rating: &rating,
+        content: &content,
+    };
+
+    let document = templates.render(get_wiki_slug(host), &context);
+
+    html_response!(HttpResponse::build(status), document)
+}
+
+/// Wraps page contents in the site's printer-friendly layout, without navigation.
+fn print_response(
+    host: Option<&str>,
+    contents: PageContents,
+    canonical_url: &str,
+    settings: &RuntimeSettings,
+    templates: &Templates,
+) -> HttpResponse {
+    let head = head_body(
+        &contents,
+        canonical_url,
+        settings.site_name(get_wiki_slug(host)),
+    );
+
+    let context = LayoutContext {
+        title: &contents.title,
+        head: &head,
+        style: &contents.style,
+        nav_top: "",
+        nav_side: "",
+        rating: "",
+        content: &contents.content,
+    };
+
+    let document = templates.render_print(get_wiki_slug(host), &context);
+
+    html_response!(HttpResponse::build(contents.status), document)
+}
+
+/// Serves only the page contents, as an HTML fragment for embedding elsewhere.
+fn bare_response(contents: PageContents) -> HttpResponse {
+    let PageContents {
+        status,
+        style,
+        content,
+        ..
+    } = contents;
+
+    let fragment = if style.is_empty() {
+        content
+    } else {
+        format!("<style>\n{}\n</style>\n{}", style, content)
+    };
+
+    html_response!(HttpResponse::build(status), fragment)
+}
+
+/// Builds the contents of a page for the requested mode.
+async fn page_contents(
+    slug: &str,
+    mode: PageMode,
+    page: Option<(Page, String)>,
+    ctx: &RenderContext<'_>,
+) -> StdResult<PageContents, HttpResponse> {
+    let RenderContext {
+        deepwell,
+        settings,
+        wiki_id,
+        ..
+    } = *ctx;
+
+    let (page, wikitext) = match (mode, page) {
+        (PageMode::Edit, None) => {
+            debug!("Page '{}' does not exist, creating", slug);
+
+            return Ok(PageContents::new(slug, edit_body(slug, slug, None)));
+        }
+        (_, None) => {
+            debug!("Page '{}' does not exist", slug);
+
+            let mut contents = PageContents::new(slug, missing_page_body(slug));
+            contents.status = http::StatusCode::NOT_FOUND;
+            return Ok(contents);
+        }
+        (_, Some(page)) => page,
+    };
+
+    let contents = match mode {
+        PageMode::Edit => {
+            let existing = Some((wikitext.as_str(), page.revision_id()));
+
+            PageContents::new(page.title(), edit_body(slug, page.title(), existing))
+        }
+        PageMode::History => PageContents::new(page.title(), history_body(slug)),
+        PageMode::Revision(number) => {
+            let result = deepwell
+                .claim()
+                .await
+                .get_page_revision(wiki_id, String::from(slug), number)
+                .await;
+
+            match result {
+                Ok(Ok(Some((revision, wikitext)))) => {
+                    let output = render_page_cached(
+                        ctx,
+                        slug,
+                        revision.id(),
+                        revision_info(&revision),
+                        wikitext,
+                    )
+                    .await?;
+
+                    let content = revision_body(slug, number, Some(&output.html));
+                    let mut contents = PageContents::new(revision.title(), content);
+                    contents.style.push_str(&output.style);
+                    contents
+                }
+                Ok(Ok(None)) => {
+                    debug!("Page '{}' has no revision {}", slug, number);
+
+                    let content = revision_body(slug, number, None);
+                    let mut contents = PageContents::new(page.title(), content);
+                    contents.status = http::StatusCode::NOT_FOUND;
+                    contents
+                }
+                Ok(Err(error)) => {
+                    warn!(
+                        "Failed to retrieve revision {} of '{}': {}",
+                        number, slug, error
+                    );
+
+                    return Err(HttpResponse::InternalServerError().json(error));
+                }
+                Err(error) => {
+                    let error = Error::ServiceTransport(error).to_sendable();
+
+                    return Err(HttpResponse::BadGateway().json(error));
+                }
+            }
+        }
+        PageMode::Source => PageContents::new(page.title(), source_body(&wikitext)),
+        PageMode::NoRender => PageContents::new(page.title(), String::new()),
+        PageMode::View { offset } if offset > 0 => {
+            debug!("Page '{}' has nothing to show at offset {}", slug, offset);
+
+            let mut contents = PageContents::new(page.title(), offset_body(slug, offset));
+            contents.status = http::StatusCode::BAD_REQUEST;
+            contents
+        }
+        PageMode::View { .. } | PageMode::Print | PageMode::Bare => {
+            let output =
+                render_page_cached(ctx, slug, page.revision_id(), page_info(&page), wikitext)
+                    .await?;
+
+            // Printed and bare pages only have the page itself
+            let mut contents = match mode {
+                PageMode::View { .. } => {
+                    let votes = get_votes(wiki_id, slug, &mut deepwell.claim().await).await?;
+                    // Breadcrumbs only show public pages, the same as includes
+                    let ancestors = readable_ancestors(&output.ancestors, Role::Guest, settings);
+                    let mut content = breadcrumbs_body(&ancestors, page.title());
+                    content.push_str(&output.html);
+
+                    // The rating isn't part of the cached render, since it changes often
+                    let mut contents = PageContents::new(page.title(), content);
+                    contents.rating = rating_body(slug, &Rating::from_votes(&votes));
+                    contents
+                }
+                _ => PageContents::new(page.title(), output.html.clone()),
+            };
+
+            contents.style.push_str(&output.style);
+            // Described by what the page shows, so hidden parts of the source aren't exposed
+            contents.description = html_text(&output.html);
+            contents
+        }
+    };
+
+    Ok(contents)
+}
+
+/// Gets the canonical URL for a path, which is its normalized form on the wiki's configured URL.
+fn canonical_url(req: &HttpRequest, path: &str, settings: &RuntimeSettings) -> String {
+    let path = redirect(path).unwrap_or_else(|| String::from(path));
+    let base = settings.site_url(get_wiki_slug(get_host(req)));
+
+    format!("{}{}", base, path)
+}
+
+/// Builds the path to a page's new slug, keeping the arguments from the original request.
+/// Everything taken from the request is encoded again, so it can't change the redirect's target.
+fn redirect_path(target: &str, page_req: &PageRequest) -> String {
+    let encode = |segment: &str| encode_path_segment(&decode_path_segment(segment));
+
+    let mut arguments: Vec<_> = page_req.arguments.iter().collect();
+    arguments.sort_by_key(|(key, _)| *key);
+
+    let mut path = format!("/{}", encode_path_segment(target));
+
+    for (key, value) in arguments {
+        path.push('/');
+        path.push_str(&encode(key));
+
+        match value {
+            ArgumentValue::String(value) => path.push_str(&format!("/{}", encode(value))),
+            ArgumentValue::Integer(value) => path.push_str(&format!("/{}", value)),
+            ArgumentValue::Boolean(value) => path.push_str(&format!("/{}", value)),
+            ArgumentValue::Null => (),
+        }
+    }
+
+    path
+}
+
+/// Gets the full page slug, including categories, from a request.
+/// For instance, `/component:license-box` has the slug `component:license-box`.
+fn get_page_slug(page_req: &PageRequest) -> String {
+    let mut slug = String::new();
+
+    for category in &page_req.categories {
+        slug.push_str(category);
+        slug.push(':');
+    }
+
+    slug.push_str(page_req.slug);
+    slug
+}
@@ -0,0 +1,274 @@
+/*
+ * route/page/mode.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Determines how a page should be served from its Wikidot-style URL arguments.
+//!
+//! For instance `/scp-173/edit/true` opens the editor, and `/scp-173/revision/12`
+//! shows revision 12 of the page. As with Wikidot, arguments which are not
+//! recognized are ignored.
+
+use std::collections::HashMap;
+use wikidot_path::ArgumentValue;
+
+/// The different ways a page can be served.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PageMode {
+    /// Render the page normally.
+    ///
+    /// Only system pages which list things, such as recent changes, use the offset.
+    /// Other pages have nothing to page through, so they reject a nonzero offset.
+    View { offset: u32 },
+
+    /// Show the page without rendering its contents.
+    /// Used to recover pages which break when rendered.
+    NoRender,
+
+    /// Show the page's wikitext.
+    Source,
+
+    /// Open the page editor.
+    Edit,
+
+    /// Show the page's revision history.
+    History,
+
+    /// Show a particular revision of the page.
+    Revision(u32),
+
+    /// Render the page for printing, without the site layout.
+    Print,
+
+    /// Render only the page contents, as an HTML fragment for embedding elsewhere.
+    Bare,
+}
+
+impl PageMode {
+    /// Determines the page mode from the parsed URL arguments.
+    ///
+    /// If several modes are specified, the first in this order wins:
+    /// `edit`, `history`, `source`, `revision`, `norender`, `print`, `bare`, `offset`.
+    pub fn from_arguments(arguments: &HashMap<&str, ArgumentValue>) -> Self {
+        let flag = |key| arguments.get(key).map(is_true).unwrap_or(false);
+        let number = |key| arguments.get(key).and_then(as_number);
+
+        if flag("edit") {
+            return PageMode::Edit;
+        }
+
+        if flag("history") {
+            return PageMode::History;
+        }
+
+        if flag("source") {
+            return PageMode::Source;
+        }
+
+        if let Some(revision) = number("revision") {
+            return PageMode::Revision(revision);
+        }
+
+        if flag("norender") {
+            return PageMode::NoRender;
+        }
+
+        if flag("print") {
+            return PageMode::Print;
+        }
+
+        if flag("bare") {
+            return PageMode::Bare;
+        }
+
+        PageMode::View {
+            offset: number("offset").unwrap_or(0),
+        }
+    }
+
+    /// Whether search engines should index pages served in this mode.
+    /// Printed and bare pages are left out, since they duplicate the normal page.
+    pub fn is_indexable(self) -> bool {
+        !matches!(
+            self,
+            PageMode::Edit
+                | PageMode::History
+                | PageMode::Revision(_)
+                | PageMode::Print
+                | PageMode::Bare
+        )
+    }
+}
+
+impl Default for PageMode {
+    #[inline]
+    fn default() -> Self {
+        PageMode::View { offset: 0 }
+    }
+}
+
+fn is_true(value: &ArgumentValue) -> bool {
+    match value {
+        ArgumentValue::Boolean(value) => *value,
+        ArgumentValue::Integer(value) => *value != 0,
+        ArgumentValue::String(value) => value.eq_ignore_ascii_case("true"),
+        ArgumentValue::Null => false,
+    }
+}
+
+fn as_number(value: &ArgumentValue) -> Option<u32> {
+    match value {
+        ArgumentValue::Integer(value) if *value >= 0 => Some(*value as u32),
+        ArgumentValue::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! mode {
+        ($($key:expr => $value:expr),* $(,)?) => {{
+            let mut arguments = HashMap::new();
+            $(arguments.insert($key, $value);)*
+            PageMode::from_arguments(&arguments)
+        }};
+    }
+
+    #[test]
+    fn view() {
+        assert_eq!(mode!(), PageMode::View { offset: 0 });
+        assert_eq!(mode!(), PageMode::default());
+        assert_eq!(
+            mode!("unknown" => ArgumentValue::Boolean(true)),
+            PageMode::View { offset: 0 },
+        );
+    }
+
+    #[test]
+    fn norender() {
+        assert_eq!(
+            mode!("norender" => ArgumentValue::Boolean(true)),
+            PageMode::NoRender,
+        );
+        assert_eq!(
+            mode!("norender" => ArgumentValue::Integer(1)),
+            PageMode::NoRender,
+        );
+        assert_eq!(
+            mode!("norender" => ArgumentValue::Boolean(false)),
+            PageMode::View { offset: 0 },
+        );
+    }
+
+    #[test]
+    fn noredirect() {
+        // Not a mode of its own, so it is ignored like any other unknown argument
+        assert_eq!(
+            mode!("noredirect" => ArgumentValue::Boolean(true)),
+            PageMode::View { offset: 0 },
+        );
+        assert_eq!(
+            mode!(
+                "noredirect" => ArgumentValue::Boolean(true),
+                "edit" => ArgumentValue::Boolean(true),
+            ),
+            PageMode::Edit,
+        );
+    }
+
+    #[test]
+    fn offset() {
+        assert_eq!(
+            mode!("offset" => ArgumentValue::Integer(2)),
+            PageMode::View { offset: 2 },
+        );
+        assert_eq!(
+            mode!("offset" => ArgumentValue::String("3".into())),
+            PageMode::View { offset: 3 },
+        );
+        assert_eq!(
+            mode!(
+                "offset" => ArgumentValue::Integer(2),
+                "norender" => ArgumentValue::Boolean(true),
+            ),
+            PageMode::NoRender,
+        );
+    }
+
+    #[test]
+    fn printer_friendly() {
+        // `/printer--friendly/{page}` is served as `/{page}/print/true`
+        assert_eq!(
+            mode!("print" => ArgumentValue::Boolean(true)),
+            PageMode::Print,
+        );
+        assert_eq!(
+            mode!(
+                "print" => ArgumentValue::Boolean(true),
+                "bare" => ArgumentValue::Boolean(true),
+            ),
+            PageMode::Print,
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            mode!("revision" => ArgumentValue::Integer(12)),
+            PageMode::Revision(12),
+        );
+        assert_eq!(
+            mode!(
+                "revision" => ArgumentValue::Integer(12),
+                "history" => ArgumentValue::Boolean(true),
+                "edit" => ArgumentValue::Boolean(true),
+            ),
+            PageMode::Edit,
+        );
+    }
+
+    #[test]
+    fn bad_input() {
+        assert_eq!(
+            mode!("offset" => ArgumentValue::Integer(-1)),
+            PageMode::View { offset: 0 },
+        );
+        assert_eq!(
+            mode!("offset" => ArgumentValue::String("abc".into())),
+            PageMode::View { offset: 0 },
+        );
+        assert_eq!(
+            mode!("revision" => ArgumentValue::String("abc".into())),
+            PageMode::View { offset: 0 },
+        );
+        assert_eq!(
+            mode!("revision" => ArgumentValue::Null),
+            PageMode::View { offset: 0 },
+        );
+        assert_eq!(
+            mode!("edit" => ArgumentValue::String("yes".into())),
+            PageMode::View { offset: 0 },
+        );
+        assert_eq!(
+            mode!("edit" => ArgumentValue::Null),
+            PageMode::View { offset: 0 },
+        );
+    }
+}
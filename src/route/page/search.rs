@@ -0,0 +1,131 @@
+/*
+ * route/page/search.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The `/search` page, for searching page titles and contents.
+
+use super::super::prelude::*;
+use super::super::render::RenderContext;
+use super::document::PageContents;
+use super::{canonical_url, layout_response, PageServices};
+use crate::remote::DeepwellPool;
+use crate::search::{SearchFilter, SearchIndex};
+use crate::tags::TagQuery;
+use actix_identity::Identity;
+
+/// The most results shown on the search page.
+const MAX_SEARCH_RESULTS: usize = 50;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchPageInput {
+    #[serde(default)]
+    q: String,
+    category: Option<String>,
+    tags: Option<String>,
+    required_tags: Option<String>,
+}
+
+pub async fn search_page(
+    req: HttpRequest,
+    id: Identity,
+    arg: web::Query<SearchPageInput>,
+    deepwell: web::Data<DeepwellPool>,
+    services: web::Data<PageServices>,
+    search: web::Data<SearchIndex>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    let host = get_host(&req);
+    let SearchPageInput {
+        q,
+        category,
+        tags,
+        required_tags,
+    } = arg.into_inner();
+
+    info!("GET search '{}' [{}]", q, host.unwrap_or("none"));
+
+    let (role, wiki_id) = {
+        let mut deepwell = deepwell.claim().await;
+        let role = try_resp!(get_role(id, host, &mut deepwell).await);
+        let wiki_id = try_resp!(get_wiki_id(host, &mut deepwell).await);
+
+        (role, wiki_id)
+    };
+
+    if !q.trim().is_empty() {
+        try_resp!(load_search_index(&search, wiki_id, &deepwell).await);
+    }
+
+    let mut content = format!(
+        "<form class=\"search-box\" action=\"/search\" method=\"get\">\n\
+         <input type=\"text\" name=\"q\" value=\"{}\" />\n\
+         <input type=\"submit\" value=\"Search\" />\n\
+         </form>\n",
+        escape_html(&q),
+    );
+
+    if !q.trim().is_empty() {
+        let tags = TagQuery::from_params(tags.as_deref(), required_tags.as_deref());
+        let filter = SearchFilter {
+            category: category.as_ref().map(|category| category.as_str()),
+            tags: tags.as_ref(),
+        };
+
+        let allowed = |slug: &str| read_role(&settings, slug) <= role;
+        let (total, results) = search.search(wiki_id, &q, &filter, allowed, 0, MAX_SEARCH_RESULTS);
+
+        content.push_str(&format!(
+            "<p class=\"search-count\">{} results for <em>{}</em></p>\n\
+             <div class=\"search-results\">\n",
+            total,
+            escape_html(&q),
+        ));
+
+        for result in &results {
+            content.push_str(&format!(
+                "<div class=\"item\">\n\
+                 <div class=\"title\"><a href=\"/{}\">{}</a></div>\n\
+                 <div class=\"preview\">{}</div>\n\
+                 </div>\n",
+                encode_path_segment(&result.slug),
+                escape_html(&result.title),
+                result.snippet,
+            ));
+        }
+
+        content.push_str("</div>");
+    }
+
+    // Search results are different for every query, so they shouldn't be indexed
+    let mut contents = PageContents::new("Search", content);
+    contents.indexable = false;
+
+    let canonical_url = canonical_url(&req, "/search", &settings);
+
+    let ctx = RenderContext {
+        deepwell: &deepwell,
+        ftml: &services.ftml,
+        cache: &services.cache,
+        settings: &settings,
+        wiki_id,
+    };
+
+    layout_response(host, contents, &canonical_url, &ctx, &services.templates).await
+}
@@ -0,0 +1,245 @@
+/*
+ * route/page/system.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Built-in `system:` pages, which are generated rather than stored in DEEPWELL.
+
+use super::super::changes::{get_recent_changes, ChangeFilter, ChangeType};
+use super::super::prelude::*;
+use super::document::PageContents;
+use crate::page_list::PageListCache;
+use crate::tags::{count_tags, normalize_tag};
+use deepwell_core::roles::Role;
+use deepwell_core::types::WikiId;
+use deepwell_rpc::Client as DeepwellClient;
+use std::convert::TryFrom;
+use wikidot_path::{ArgumentValue, Request as PageRequest};
+
+/// How many changes are shown on each page of `system:recent-changes`.
+const RECENT_CHANGES_PER_PAGE: u32 = 50;
+
+/// Determines if a slug is one of the built-in system pages.
+pub fn is_system_page(slug: &str) -> bool {
+    matches!(slug, "system:page-tags" | "system:recent-changes")
+}
+
+/// Builds the contents of a system page, for a user with the given role.
+pub async fn system_page(
+    slug: &str,
+    page_req: &PageRequest<'_>,
+    role: Role,
+    settings: &RuntimeSettings,
+    page_lists: &PageListCache,
+    deepwell: &mut DeepwellClient,
+    wiki_id: WikiId,
+) -> StdResult<PageContents, HttpResponse> {
+    match slug {
+        "system:page-tags" => {
+            page_tags(page_req, role, settings, page_lists, deepwell, wiki_id).await
+        }
+        "system:recent-changes" => {
+            recent_changes(page_req, role, settings, deepwell, wiki_id).await
+        }
+        _ => unreachable!("Not a system page: '{}'", slug),
+    }
+}
+
+/// Gets a page argument as a string, if present.
+fn string_argument(page_req: &PageRequest<'_>, key: &str) -> Option<String> {
+    match page_req.arguments.get(key) {
+        Some(ArgumentValue::String(value)) => Some(decode_path_segment(value)),
+        Some(ArgumentValue::Integer(value)) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Lists the pages with a tag, or all tags if no tag is given.
+/// Only pages the user can read are included.
+async fn page_tags(
+    page_req: &PageRequest<'_>,
+    role: Role,
+    settings: &RuntimeSettings,
+    page_lists: &PageListCache,
+    deepwell: &mut DeepwellClient,
+    wiki_id: WikiId,
+) -> StdResult<PageContents, HttpResponse> {
+    let tag = string_argument(page_req, "tag").map(|tag| normalize_tag(&tag));
+    let pages = get_cached_pages(wiki_id, page_lists, deepwell).await?;
+    let readable = pages
+        .iter()
+        .filter(|page| read_role(settings, page.slug()) <= role);
+
+    let mut content = String::new();
+
+    match tag {
+        Some(tag) => {
+            debug!("Listing pages with tag '{}'", tag);
+
+            let mut tagged: Vec<_> = readable
+                .filter(|page| page.tags().iter().any(|page_tag| page_tag == &tag))
+                .collect();
+
+            tagged.sort_by(|a, b| a.title().cmp(b.title()));
+
+            content.push_str(&format!(
+                "<h2>Pages tagged <em>{}</em></h2>\n<ul class=\"tagged-pages\">\n",
+                escape_html(&tag),
+            ));
+
+            for page in tagged {
+                content.push_str(&format!(
+                    "<li><a href=\"/{}\">{}</a></li>\n",
+                    escape_html(page.slug()),
+                    escape_html(page.title()),
+                ));
+            }
+
+            content.push_str("</ul>");
+        }
+        None => {
+            debug!("Listing all tags");
+
+            content.push_str("<div class=\"pages-tag-cloud-box\">\n");
+
+            for (tag, count) in count_tags(readable) {
+                content.push_str(&format!(
+                    "<a class=\"tag\" href=\"/system:page-tags/tag/{}\">{}</a> ({})\n",
+                    encode_path_segment(tag),
+                    escape_html(tag),
+                    count,
+                ));
+            }
+
+            content.push_str("</div>");
+        }
+    }
+
+    Ok(PageContents::new("Page Tags", content))
+}
+
+/// Lists recent changes across the site, newest first.
+async fn recent_changes(
+    page_req: &PageRequest<'_>,
+    role: Role,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+    wiki_id: WikiId,
+) -> StdResult<PageContents, HttpResponse> {
+    let filter = ChangeFilter {
+        category: string_argument(page_req, "category"),
+        user: string_argument(page_req, "user"),
+        change_type: string_argument(page_req, "type").and_then(|value| ChangeType::parse(&value)),
+    };
+
+    let offset = match page_req.arguments.get("offset") {
+        Some(ArgumentValue::Integer(offset)) => u32::try_from(*offset).unwrap_or(0),
+        _ => 0,
+    };
+
+    debug!("Listing recent changes from {} with {:?}", offset, filter);
+
+    let result = get_recent_changes(
+        wiki_id,
+        role,
+        &filter,
+        offset,
+        RECENT_CHANGES_PER_PAGE,
+        settings,
+        deepwell,
+    )
+    .await?;
+
+    // Builds a link to this page with the same filters
+    let link = |change_type: Option<ChangeType>, offset: u32| {
+        let mut path = String::from("/system:recent-changes");
+
+        if let Some(ref category) = filter.category {
+            path.push_str(&format!("/category/{}", encode_path_segment(category)));
+        }
+
+        if let Some(ref user) = filter.user {
+            path.push_str(&format!("/user/{}", encode_path_segment(user)));
+        }
+
+        if let Some(change_type) = change_type {
+            path.push_str(&format!("/type/{}", change_type.name()));
+        }
+
+        if offset > 0 {
+            path.push_str(&format!("/offset/{}", offset));
+        }
+
+        path
+    };
+
+    let mut content = String::from("<div class=\"changes-filter\">\n");
+    content.push_str(&format!("<a href=\"{}\">all</a>\n", link(None, 0)));
+
+    for change_type in &[
+        ChangeType::Created,
+        ChangeType::Edited,
+        ChangeType::Renamed,
+        ChangeType::Tags,
+        ChangeType::Deleted,
+    ] {
+        content.push_str(&format!(
+            "<a href=\"{}\">{}</a>\n",
+            link(Some(*change_type), 0),
+            change_type.name(),
+        ));
+    }
+
+    content.push_str("</div>\n<table class=\"changes-list\">\n");
+
+    for change in &result.changes {
+        let types: Vec<&str> = change.changes.iter().map(|change| change.name()).collect();
+
+        content.push_str(&format!(
+            "<tr>\n\
+             <td class=\"date\">{}</td>\n\
+             <td class=\"page\"><a href=\"/{}\">{}</a></td>\n\
+             <td class=\"flags\">{}</td>\n\
+             <td class=\"user\">{}</td>\n\
+             <td class=\"comment\">{}</td>\n\
+             </tr>\n",
+            change.timestamp.format("%Y-%m-%d %H:%M"),
+            escape_html(&change.slug),
+            escape_html(&change.slug),
+            types.join(", "),
+            escape_html(
+                change
+                    .user_name
+                    .as_ref()
+                    .map_or("(unknown)", |name| name.as_str())
+            ),
+            escape_html(&change.comment),
+        ));
+    }
+
+    content.push_str("</table>");
+
+    if let Some(next_offset) = result.next_offset {
+        content.push_str(&format!(
+            "\n<div class=\"pager\"><a href=\"{}\">Older changes</a></div>",
+            link(filter.change_type, next_offset),
+        ));
+    }
+
+    Ok(PageContents::new("Recent Changes", content))
+}
@@ -0,0 +1,249 @@
+/*
+ * route/pages.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers to fetch pages and their votes from DEEPWELL, and to load the search index from them.
+
+use crate::page_list::PageListCache;
+use crate::remote::DeepwellPool;
+use crate::search::SearchIndex;
+use crate::StdResult;
+use actix_web::HttpResponse;
+use deepwell_core::error::Error;
+use deepwell_core::types::{Page, UserId, WikiId};
+use deepwell_rpc::Client as DeepwellClient;
+use futures::stream::{self, StreamExt};
+use std::sync::Arc;
+
+/// How many pages are fetched from DEEPWELL at once when building a search index.
+const INDEX_FETCH_CONCURRENCY: usize = 8;
+
+/// Gets all the pages in a wiki.
+pub async fn get_pages(
+    wiki_id: WikiId,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Vec<Page>, HttpResponse> {
+    debug!("Getting all pages for wiki ID {}", wiki_id);
+
+    match deepwell.get_pages(wiki_id).await {
+        Ok(Ok(pages)) => Ok(pages),
+        Ok(Err(error)) => {
+            warn!("Failed to get pages for wiki ID {}: {}", wiki_id, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Gets all the pages in a wiki, from the cache if they've already been fetched.
+pub async fn get_cached_pages(
+    wiki_id: WikiId,
+    page_lists: &PageListCache,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Arc<Vec<Page>>, HttpResponse> {
+    if let Ok(pages) = page_lists.get(wiki_id) {
+        return Ok(pages);
+    }
+
+    // Only one request fetches the pages, any others wait for it
+    let loading = page_lists.load_lock(wiki_id);
+    let _guard = loading.lock().await;
+
+    let generation = match page_lists.get(wiki_id) {
+        Ok(pages) => return Ok(pages),
+        Err(generation) => generation,
+    };
+
+    let pages = get_pages(wiki_id, deepwell).await?;
+
+    Ok(page_lists.insert(wiki_id, generation, pages))
+}
+
+/// Gets a page, returning `404` if it doesn't exist.
+pub async fn get_existing_page(
+    wiki_id: WikiId,
+    slug: &str,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Page, HttpResponse> {
+    match deepwell.get_page(wiki_id, slug.into()).await {
+        Ok(Ok(Some((page, _)))) => Ok(page),
+        Ok(Ok(None)) => {
+            let error = Error::StaticMsg("Page does not exist").to_sendable();
+
+            Err(HttpResponse::NotFound().json(error))
+        }
+        Ok(Err(error)) => Err(HttpResponse::InternalServerError().json(error)),
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Gets all the votes cast on a page, as pairs of user ID and vote value.
+pub async fn get_votes(
+    wiki_id: WikiId,
+    slug: &str,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Vec<(UserId, i8)>, HttpResponse> {
+    debug!("Getting votes for page '{}' in wiki ID {}", slug, wiki_id);
+
+    match deepwell.get_page_votes(wiki_id, slug.into()).await {
+        Ok(Ok(votes)) => Ok(votes),
+        Ok(Err(error)) => {
+            warn!("Failed to get votes for page '{}': {}", slug, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Gets the slug a page was renamed to, if a redirect from this slug exists.
+pub async fn get_redirect(
+    wiki_id: WikiId,
+    slug: &str,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Option<String>, HttpResponse> {
+    match deepwell.get_page_redirect(wiki_id, slug.into()).await {
+        Ok(Ok(target)) => Ok(target),
+        Ok(Err(error)) => {
+            warn!("Failed to get redirect for page '{}': {}", slug, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Fetches the wikitext of a page to add it to the search index.
+/// Returns `None` if it was removed since the page list was fetched.
+async fn get_indexed_page(
+    wiki_id: WikiId,
+    page: &Page,
+    deepwell: &DeepwellPool,
+) -> StdResult<Option<(&Page, String)>, HttpResponse> {
+    let result = deepwell
+        .claim()
+        .await
+        .get_page(wiki_id, page.slug().into())
+        .await;
+
+    match result {
+        Ok(Ok(Some((_, wikitext)))) => Ok(Some((page, wikitext))),
+        Ok(Ok(None)) => {
+            debug!("Page '{}' removed while indexing", page.slug());
+
+            Ok(None)
+        }
+        Ok(Err(error)) => {
+            warn!(
+                "Failed to get page '{}' for indexing: {}",
+                page.slug(),
+                error
+            );
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Loads a wiki's search index, if it hasn't been already.
+///
+/// Only one request loads each wiki's index, any others wait for it.
+/// Pages are fetched concurrently, using clients from the pool,
+/// so the caller must not be holding one.
+pub async fn load_search_index(
+    search: &SearchIndex,
+    wiki_id: WikiId,
+    deepwell: &DeepwellPool,
+) -> StdResult<(), HttpResponse> {
+    if search.is_loaded(wiki_id) {
+        return Ok(());
+    }
+
+    let loading = search.load_lock(wiki_id);
+    let _guard = loading.lock().await;
+
+    if search.is_loaded(wiki_id) {
+        return Ok(());
+    }
+
+    info!("Building search index for wiki ID {}", wiki_id);
+
+    // Changes from here on may be newer than the pages fetched
+    search.start_load(wiki_id);
+
+    let pages = {
+        let mut deepwell = deepwell.claim().await;
+
+        get_pages(wiki_id, &mut deepwell).await
+    };
+
+    let pages = match pages {
+        Ok(pages) => pages,
+        Err(resp) => {
+            search.cancel_load(wiki_id);
+            return Err(resp);
+        }
+    };
+
+    let results: Vec<_> = stream::iter(&pages)
+        .map(|page| get_indexed_page(wiki_id, page, deepwell))
+        .buffer_unordered(INDEX_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    let contents = match results.into_iter().collect::<StdResult<Vec<_>, _>>() {
+        Ok(contents) => contents,
+        Err(resp) => {
+            search.cancel_load(wiki_id);
+            return Err(resp);
+        }
+    };
+
+    search.load(
+        wiki_id,
+        contents
+            .iter()
+            .flatten()
+            .map(|(page, wikitext)| (page.slug(), page.title(), page.tags(), wikitext.as_str())),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+/*
+ * route/parent.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers for the page parent/child hierarchy.
+
+use super::prelude::*;
+use crate::cache::Ancestor;
+use deepwell_core::error::Error;
+use deepwell_core::roles::Role;
+use deepwell_core::types::WikiId;
+use deepwell_rpc::Client as DeepwellClient;
+
+/// How many parents up the hierarchy to follow.
+///
+/// This also bounds cycle detection, so deeper hierarchies are rejected.
+pub const MAX_PARENT_DEPTH: usize = 16;
+
+/// Gets the slug of a page's parent, if it has one.
+pub async fn get_parent(
+    wiki_id: WikiId,
+    slug: &str,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Option<String>, HttpResponse> {
+    match deepwell.get_page_parent(wiki_id, slug.into()).await {
+        Ok(Ok(parent)) => Ok(parent),
+        Ok(Err(error)) => {
+            warn!("Failed to get parent of page '{}': {}", slug, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Gets the chain of parents above a page, starting from the topmost one.
+///
+/// The chain is cut short if it loops back on itself or exceeds `MAX_PARENT_DEPTH`,
+/// or if any parent no longer exists.
+pub async fn get_ancestors(
+    wiki_id: WikiId,
+    slug: &str,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<Vec<Ancestor>, HttpResponse> {
+    let mut ancestors: Vec<Ancestor> = Vec::new();
+    let mut current = get_parent(wiki_id, slug, deepwell).await?;
+
+    while let Some(parent) = current {
+        if parent == slug || ancestors.iter().any(|ancestor| ancestor.slug == parent) {
+            warn!("Parent cycle found above page '{}' at '{}'", slug, parent);
+            break;
+        }
+
+        if ancestors.len() >= MAX_PARENT_DEPTH {
+            debug!("Page hierarchy above '{}' is too deep, stopping", slug);
+            break;
+        }
+
+        let title = match deepwell.get_page(wiki_id, parent.clone()).await {
+            Ok(Ok(Some((page, _)))) => String::from(page.title()),
+            Ok(Ok(None)) => {
+                debug!("Parent page '{}' does not exist", parent);
+                break;
+            }
+            Ok(Err(error)) => return Err(HttpResponse::InternalServerError().json(error)),
+            Err(error) => {
+                let error = Error::ServiceTransport(error).to_sendable();
+
+                return Err(HttpResponse::BadGateway().json(error));
+            }
+        };
+
+        current = get_parent(wiki_id, &parent, deepwell).await?;
+        ancestors.push(Ancestor {
+            slug: parent,
+            title,
+        });
+    }
+
+    ancestors.reverse();
+    Ok(ancestors)
+}
+
+/// Leaves out the ancestors `role` isn't allowed to read, so their titles aren't shown.
+///
+/// Rendered pages keep every ancestor, since changes to any of them affect the page.
+pub fn readable_ancestors(
+    ancestors: &[Ancestor],
+    role: Role,
+    settings: &RuntimeSettings,
+) -> Vec<Ancestor> {
+    ancestors
+        .iter()
+        .filter(|ancestor| read_role(settings, &ancestor.slug) <= role)
+        .cloned()
+        .collect()
+}
+
+/// Determines if making `parent` the parent of `slug` would create a cycle.
+///
+/// Returns `true` if `slug` is `parent` itself or any page above it,
+/// or if the hierarchy is too deep to check.
+pub async fn creates_cycle(
+    wiki_id: WikiId,
+    slug: &str,
+    parent: &str,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<bool, HttpResponse> {
+    let mut current = Some(String::from(parent));
+
+    for _ in 0..=MAX_PARENT_DEPTH {
+        match current {
+            Some(ref page) if page == slug => return Ok(true),
+            Some(ref page) => current = get_parent(wiki_id, page, deepwell).await?,
+            None => return Ok(false),
+        }
+    }
+
+    Ok(current.is_some())
+}
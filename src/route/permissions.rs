@@ -18,12 +18,14 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use crate::config::RuntimeSettings;
 use crate::session::CookieSession;
 use crate::StdResult;
 use actix_identity::Identity;
 use actix_web::HttpResponse;
 use deepwell_core::error::Error;
 use deepwell_core::roles::Role;
+use deepwell_core::types::UserId;
 use deepwell_rpc::Client as DeepwellClient;
 
 pub async fn get_role(
@@ -62,3 +64,76 @@ pub async fn check_role(
         Err(HttpResponse::Forbidden().json(error))
     }
 }
+
+/// Gets the ID of the currently logged-in user, verifying their session.
+pub async fn get_user_id(
+    id: &Identity,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<UserId, HttpResponse> {
+    match id.identity() {
+        None => {
+            let error = Error::NotLoggedIn.to_sendable();
+
+            Err(HttpResponse::Unauthorized().json(error))
+        }
+        Some(ref data) => {
+            let session = CookieSession::read(data)?;
+            session.verify(deepwell).await?;
+
+            Ok(session.user_id)
+        }
+    }
+}
+
+/// Gets the role required to read the given page, based on its categories.
+///
+/// A slug can have several categories, such as `deleted:1:admin:page` for a deleted
+/// admin page, so this is the strictest role any of them need.
+pub fn read_role(settings: &RuntimeSettings, slug: &str) -> Role {
+    let categories = match slug.rfind(':') {
+        Some(index) => &slug[..index],
+        None => return Role::Guest,
+    };
+
+    categories
+        .split(':')
+        .filter_map(|category| settings.read_roles.get(&category.to_ascii_lowercase()))
+        .fold(
+            Role::Guest,
+            |strictest, &role| {
+                if role > strictest {
+                    role
+                } else {
+                    strictest
+                }
+            },
+        )
+}
+
+/// Determines whether the current user is allowed to read the given page.
+pub async fn can_read(
+    slug: &str,
+    id: Identity,
+    host: Option<&str>,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<bool, HttpResponse> {
+    match read_role(settings, slug) {
+        Role::Guest => Ok(true),
+        role => Ok(get_role(id, host, deepwell).await? >= role),
+    }
+}
+
+/// Checks that the current user is allowed to read the given page.
+pub async fn check_read(
+    slug: &str,
+    id: Identity,
+    host: Option<&str>,
+    settings: &RuntimeSettings,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<(), HttpResponse> {
+    match read_role(settings, slug) {
+        Role::Guest => Ok(()),
+        role => check_role(role, id, host, deepwell).await,
+    }
+}
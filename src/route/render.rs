@@ -0,0 +1,244 @@
+/*
+ * route/render.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers for rendering wikitext with ftml.
+
+use super::parent::get_ancestors;
+use super::prelude::*;
+use crate::cache::{CachedRender, RenderCache};
+use crate::include::{expand, Expansion, IncludedPages};
+use crate::remote::{DeepwellPool, FtmlPool};
+use deepwell_core::error::Error;
+use deepwell_core::roles::Role;
+use deepwell_core::types::{Page, Revision, RevisionId, WikiId};
+use ftml_rpc::{HtmlOutput, PageInfoOwned};
+use futures::stream::{self, StreamExt};
+use std::sync::Arc;
+
+/// How many included pages are fetched from DEEPWELL at once.
+const INCLUDE_FETCH_CONCURRENCY: usize = 4;
+
+/// Everything needed to render pages for a request.
+#[derive(Debug, Copy, Clone)]
+pub struct RenderContext<'a> {
+    pub deepwell: &'a DeepwellPool,
+    pub ftml: &'a FtmlPool,
+    pub cache: &'a RenderCache,
+    pub settings: &'a RuntimeSettings,
+    pub wiki_id: WikiId,
+}
+
+/// Builds the page information ftml needs to render a page.
+///
+/// The rating is left out, since renders are cached and votes change far more
+/// often than pages. It is added by the site layout instead.
+pub fn page_info(page: &Page) -> PageInfoOwned {
+    PageInfoOwned {
+        title: page.title().into(),
+        alt_title: page.alt_title().map(String::from),
+        header: None,
+        subheader: None,
+        rating: 0.0,
+        tags: page.tags().to_vec(),
+    }
+}
+
+/// Builds the page information for an older revision of a page,
+/// using the title and tags the page had at that revision.
+pub fn revision_info(revision: &Revision) -> PageInfoOwned {
+    PageInfoOwned {
+        title: revision.title().into(),
+        alt_title: revision.alt_title().map(String::from),
+        header: None,
+        subheader: None,
+        rating: 0.0,
+        tags: revision.tags().to_vec(),
+    }
+}
+
+/// Renders wikitext into HTML.
+pub async fn render_wikitext(
+    ftml: &FtmlPool,
+    page_info: PageInfoOwned,
+    wikitext: String,
+) -> StdResult<HtmlOutput, HttpResponse> {
+    let title = page_info.title.clone();
+    let result = ftml.claim().await.render(page_info, wikitext).await;
+
+    match result {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(error)) => {
+            error!("Failed to render page '{}': {}", title, error);
+
+            let error = Error::StaticMsg("Unable to render page").to_sendable();
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Fetches the wikitext of an included page, or `None` if it doesn't exist.
+async fn get_included_page(
+    ctx: &RenderContext<'_>,
+    slug: String,
+) -> StdResult<(String, Option<String>), HttpResponse> {
+    let mut deepwell = ctx.deepwell.claim().await;
+
+    match deepwell.get_page(ctx.wiki_id, slug.clone()).await {
+        Ok(Ok(page)) => Ok((slug, page.map(|(_, contents)| contents))),
+        Ok(Err(error)) => {
+            warn!("Failed to retrieve included page '{}': {}", slug, error);
+
+            Err(HttpResponse::InternalServerError().json(error))
+        }
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
+
+/// Replaces all the `[[include]]` blocks in the wikitext, fetching included pages from DEEPWELL.
+///
+/// The pages needed by each pass are fetched concurrently.
+/// Pages which can't be read by guests are treated as missing,
+/// so their contents can't be exposed by including them.
+pub async fn resolve_includes(
+    ctx: &RenderContext<'_>,
+    wikitext: &str,
+) -> StdResult<Expansion, HttpResponse> {
+    let mut pages = IncludedPages::new();
+
+    loop {
+        let expansion = expand(wikitext, &pages);
+
+        if expansion.needed.is_empty() {
+            if !expansion.missing.is_empty() {
+                debug!("Missing included pages: {:?}", expansion.missing);
+            }
+
+            return Ok(expansion);
+        }
+
+        let mut needed = Vec::new();
+
+        for slug in expansion.needed {
+            if read_role(ctx.settings, &slug) != Role::Guest {
+                debug!("Not including restricted page '{}'", slug);
+
+                pages.insert(slug, None);
+            } else {
+                needed.push(slug);
+            }
+        }
+
+        let fetched: Vec<_> = stream::iter(needed)
+            .map(|slug| get_included_page(ctx, slug))
+            .buffer_unordered(INCLUDE_FETCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        for result in fetched {
+            let (slug, contents) = result?;
+
+            pages.insert(slug, contents);
+        }
+    }
+}
+
+/// Renders a revision of a page, reusing the cached output if it has been rendered before.
+///
+/// The page's breadcrumbs are fetched along with the render and cached with it.
+/// Changes to included pages and parent pages are handled by the cache itself.
+pub async fn render_page_cached(
+    ctx: &RenderContext<'_>,
+    slug: &str,
+    revision_id: RevisionId,
+    info: PageInfoOwned,
+    contents: String,
+) -> StdResult<Arc<CachedRender>, HttpResponse> {
+    if let Some(render) = ctx.cache.get(ctx.wiki_id, slug, revision_id) {
+        return Ok(render);
+    }
+
+    let Expansion {
+        wikitext,
+        missing,
+        includes,
+        ..
+    } = resolve_includes(ctx, &contents).await?;
+
+    let ancestors = {
+        let mut deepwell = ctx.deepwell.claim().await;
+
+        get_ancestors(ctx.wiki_id, slug, &mut deepwell).await?
+    };
+
+    let HtmlOutput { html, style, .. } = render_wikitext(ctx.ftml, info, wikitext).await?;
+
+    let render = CachedRender {
+        html,
+        style,
+        includes: includes.into_iter().collect(),
+        missing_includes: missing.into_iter().collect(),
+        ancestors,
+    };
+
+    Ok(ctx.cache.insert(ctx.wiki_id, slug, revision_id, render))
+}
+
+/// Renders a navigation page such as `nav:top`.
+/// Any failures are logged and result in an empty section rather than an error.
+pub async fn render_nav(ctx: &RenderContext<'_>, slug: &str) -> Option<Arc<CachedRender>> {
+    let (page, contents) = {
+        let mut deepwell = ctx.deepwell.claim().await;
+
+        match deepwell.get_page(ctx.wiki_id, slug.into()).await {
+            Ok(Ok(Some(page))) => page,
+            Ok(Ok(None)) => {
+                debug!("Navigation page '{}' does not exist", slug);
+
+                return None;
+            }
+            Ok(Err(error)) => {
+                warn!("Failed to retrieve navigation page '{}': {}", slug, error);
+
+                return None;
+            }
+            Err(error) => {
+                warn!(
+                    "Unable to reach DEEPWELL for navigation page '{}': {}",
+                    slug, error
+                );
+
+                return None;
+            }
+        }
+    };
+
+    render_page_cached(ctx, slug, page.revision_id(), page_info(&page), contents)
+        .await
+        .ok()
+}
@@ -0,0 +1,117 @@
+/*
+ * route/sitemap.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Routes for `sitemap.xml`, see `crate::sitemap`.
+
+use super::prelude::*;
+use crate::remote::DeepwellPool;
+use crate::sitemap::{Sitemap, SitemapCache, SitemapEntry};
+use deepwell_core::roles::Role;
+use std::sync::Arc;
+
+/// Gets the sitemap for this site, generating it if it isn't cached.
+///
+/// URLs use the wiki's configured URL, not the request's host.
+async fn get_sitemap(
+    req: &HttpRequest,
+    deepwell: &DeepwellPool,
+    sitemaps: &SitemapCache,
+    settings: &RuntimeSettings,
+) -> StdResult<Arc<Sitemap>, HttpResponse> {
+    let host = get_host(req);
+    let mut deepwell = deepwell.claim().await;
+    let wiki_id = get_wiki_id(host, &mut deepwell).await?;
+
+    if let Ok(sitemap) = sitemaps.get(wiki_id) {
+        return Ok(sitemap);
+    }
+
+    // Only one request generates a wiki's sitemap, any others wait for it
+    let loading = sitemaps.load_lock(wiki_id);
+    let _guard = loading.lock().await;
+
+    let generation = match sitemaps.get(wiki_id) {
+        Ok(sitemap) => return Ok(sitemap),
+        Err(generation) => generation,
+    };
+
+    debug!("Generating sitemap for wiki ID {}", wiki_id);
+
+    let pages = get_pages(wiki_id, &mut deepwell).await?;
+
+    // Only list pages which crawlers can read
+    let mut entries: Vec<SitemapEntry> = pages
+        .iter()
+        .filter(|page| read_role(settings, page.slug()) == Role::Guest)
+        .map(|page| SitemapEntry {
+            slug: String::from(page.slug()),
+            updated: page.updated_at(),
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.slug.cmp(&b.slug));
+
+    let base = settings.site_url(get_wiki_slug(host));
+    let sitemap = Sitemap::build(&base, &entries);
+
+    Ok(sitemaps.insert(wiki_id, generation, sitemap))
+}
+
+fn xml_response(body: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .body(String::from(body))
+}
+
+pub async fn sitemap_main(
+    req: HttpRequest,
+    deepwell: web::Data<DeepwellPool>,
+    sitemaps: web::Data<SitemapCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    info!("GET sitemap [{}]", get_host(&req).unwrap_or("none"));
+
+    let sitemap = try_resp!(get_sitemap(&req, &deepwell, &sitemaps, &settings).await);
+
+    xml_response(sitemap.main())
+}
+
+pub async fn sitemap_part(
+    req: HttpRequest,
+    number: web::Path<usize>,
+    deepwell: web::Data<DeepwellPool>,
+    sitemaps: web::Data<SitemapCache>,
+    settings: web::Data<RuntimeSettings>,
+) -> HttpResponse {
+    let number = number.into_inner();
+
+    info!(
+        "GET sitemap part {} [{}]",
+        number,
+        get_host(&req).unwrap_or("none"),
+    );
+
+    let sitemap = try_resp!(get_sitemap(&req, &deepwell, &sitemaps, &settings).await);
+
+    match sitemap.part(number) {
+        Some(part) => xml_response(part),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
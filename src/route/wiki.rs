@@ -0,0 +1,54 @@
+/*
+ * route/wiki.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers to determine which wiki a request is for.
+
+use crate::utils::get_wiki_slug;
+use crate::StdResult;
+use actix_web::HttpResponse;
+use deepwell_core::error::Error;
+use deepwell_core::types::WikiId;
+use deepwell_rpc::Client as DeepwellClient;
+
+pub async fn get_wiki_id(
+    host: Option<&str>,
+    deepwell: &mut DeepwellClient,
+) -> StdResult<WikiId, HttpResponse> {
+    let slug = match get_wiki_slug(host) {
+        Some(slug) => slug,
+        None => {
+            let error = Error::StaticMsg("No wiki specified by hostname").to_sendable();
+
+            return Err(HttpResponse::BadRequest().json(error));
+        }
+    };
+
+    debug!("Getting wiki ID for slug '{}'", slug);
+
+    match deepwell.get_wiki_id(slug.into()).await {
+        Ok(Ok(wiki_id)) => Ok(wiki_id),
+        Ok(Err(error)) => Err(HttpResponse::NotFound().json(error)),
+        Err(error) => {
+            let error = Error::ServiceTransport(error).to_sendable();
+
+            Err(HttpResponse::BadGateway().json(error))
+        }
+    }
+}
@@ -0,0 +1,604 @@
+/*
+ * search.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Local full-text index of page titles and contents.
+//!
+//! Each wiki's index is loaded from DEEPWELL the first time it is searched,
+//! then kept up to date as pages are changed through thaumiel. Searches match
+//! pages containing all the query's words, with matches in titles ranked higher.
+
+use crate::tags::TagQuery;
+use crate::utils::{escape_html, plain_text};
+use deepwell_core::types::WikiId;
+use futures::lock::Mutex as AsyncMutex;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+
+/// How many characters of context to show on either side of a match.
+const SNIPPET_CONTEXT: usize = 80;
+
+/// How much more a match in the title is worth than one in the contents.
+const TITLE_WEIGHT: usize = 10;
+
+/// Splits text into lowercase words for indexing and searching.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| word.to_lowercase())
+}
+
+#[derive(Debug, Clone)]
+struct Document {
+    title: String,
+    tags: Vec<String>,
+    text: Arc<str>,
+    words: HashMap<String, usize>,
+    title_words: HashSet<String>,
+}
+
+impl Document {
+    fn new(title: &str, tags: &[String], wikitext: &str) -> Self {
+        let text = plain_text(wikitext);
+        let mut words = HashMap::new();
+
+        for word in tokenize(&text) {
+            *words.entry(word).or_insert(0) += 1;
+        }
+
+        Document {
+            title: String::from(title),
+            tags: tags.to_vec(),
+            title_words: tokenize(title).collect(),
+            text: Arc::from(text),
+            words,
+        }
+    }
+
+    fn terms(&self) -> impl Iterator<Item = &String> {
+        self.words.keys().chain(&self.title_words)
+    }
+
+    fn score(&self, terms: &[String]) -> usize {
+        terms
+            .iter()
+            .map(|term| {
+                let title = if self.title_words.contains(term) {
+                    TITLE_WEIGHT
+                } else {
+                    0
+                };
+
+                title + self.words.get(term).copied().unwrap_or(0)
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SiteIndex {
+    documents: HashMap<String, Document>,
+    postings: HashMap<String, HashSet<String>>,
+}
+
+impl SiteIndex {
+    fn insert(&mut self, slug: &str, document: Document) {
+        self.remove(slug);
+
+        for term in document.terms() {
+            self.postings
+                .entry(term.clone())
+                .or_insert_with(HashSet::new)
+                .insert(String::from(slug));
+        }
+
+        self.documents.insert(String::from(slug), document);
+    }
+
+    fn remove(&mut self, slug: &str) -> Option<Document> {
+        let document = self.documents.remove(slug)?;
+
+        for term in document.terms() {
+            if let Some(slugs) = self.postings.get_mut(term) {
+                slugs.remove(slug);
+
+                if slugs.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+
+        Some(document)
+    }
+}
+
+/// Restricts which pages a search returns.
+#[derive(Debug, Default)]
+pub struct SearchFilter<'a> {
+    pub category: Option<&'a str>,
+    pub tags: Option<&'a TagQuery>,
+}
+
+/// A page which matched a search.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchResult {
+    pub slug: String,
+    pub title: String,
+    pub score: usize,
+    pub snippet: String,
+}
+
+/// A change to a page, kept to apply to an index which is still being loaded.
+#[derive(Debug)]
+enum Change {
+    Update {
+        slug: String,
+        title: String,
+        tags: Vec<String>,
+        wikitext: String,
+    },
+    Tags {
+        slug: String,
+        tags: Vec<String>,
+    },
+    Rename {
+        slug: String,
+        new_slug: String,
+    },
+    Remove {
+        slug: String,
+    },
+}
+
+impl Change {
+    fn apply(self, index: &mut SiteIndex) {
+        match self {
+            Change::Update {
+                slug,
+                title,
+                tags,
+                wikitext,
+            } => {
+                debug!("Updating search index for '{}'", slug);
+
+                index.insert(&slug, Document::new(&title, &tags, &wikitext));
+            }
+            Change::Tags { slug, tags } => {
+                if let Some(document) = index.documents.get_mut(&slug) {
+                    document.tags = tags;
+                }
+            }
+            Change::Rename { slug, new_slug } => {
+                if let Some(document) = index.remove(&slug) {
+                    index.insert(&new_slug, document);
+                }
+            }
+            Change::Remove { slug } => {
+                index.remove(&slug);
+            }
+        }
+    }
+}
+
+struct Site {
+    index: Option<SiteIndex>,
+    /// Changes made while the index is being loaded, if it is.
+    queued: Option<Vec<Change>>,
+    loading: Arc<AsyncMutex<()>>,
+}
+
+impl Site {
+    fn new() -> Self {
+        Site {
+            index: None,
+            queued: None,
+            loading: Arc::new(AsyncMutex::new(())),
+        }
+    }
+}
+
+/// The search indices for each wiki, shared between workers.
+///
+/// Only one request loads a wiki's index at a time, using `load_lock`.
+/// Changes made while it loads are queued, then applied once it has loaded,
+/// since the pages it was loaded from may be older than them.
+#[derive(Clone, Default)]
+pub struct SearchIndex {
+    sites: Arc<Mutex<HashMap<WikiId, Site>>>,
+}
+
+impl SearchIndex {
+    #[inline]
+    pub fn new() -> Self {
+        SearchIndex::default()
+    }
+
+    /// Determines if a wiki's index has been loaded yet.
+    pub fn is_loaded(&self, wiki_id: WikiId) -> bool {
+        let sites = self.sites.lock().expect("Search index lock poisoned");
+
+        match sites.get(&wiki_id) {
+            Some(site) => site.index.is_some(),
+            None => false,
+        }
+    }
+
+    /// Gets the lock held while loading a wiki's index,
+    /// so that concurrent searches wait for one load instead of each starting their own.
+    pub fn load_lock(&self, wiki_id: WikiId) -> Arc<AsyncMutex<()>> {
+        let mut sites = self.sites.lock().expect("Search index lock poisoned");
+        let site = sites.entry(wiki_id).or_insert_with(Site::new);
+
+        Arc::clone(&site.loading)
+    }
+
+    /// Starts queueing changes to a wiki's pages, before fetching them to load its index.
+    pub fn start_load(&self, wiki_id: WikiId) {
+        let mut sites = self.sites.lock().expect("Search index lock poisoned");
+        let site = sites.entry(wiki_id).or_insert_with(Site::new);
+
+        site.queued = Some(Vec::new());
+    }
+
+    /// Stops queueing changes to a wiki's pages, after its index failed to load.
+    pub fn cancel_load(&self, wiki_id: WikiId) {
+        let mut sites = self.sites.lock().expect("Search index lock poisoned");
+
+        if let Some(site) = sites.get_mut(&wiki_id) {
+            site.queued = None;
+        }
+    }
+
+    /// Builds a wiki's index from all its pages, as `(slug, title, tags, wikitext)`,
+    /// then applies any changes queued since `start_load`.
+    pub fn load<'a, I>(&self, wiki_id: WikiId, pages: I)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str, &'a [String], &'a str)>,
+    {
+        let mut index = SiteIndex::default();
+
+        for (slug, title, tags, wikitext) in pages {
+            index.insert(slug, Document::new(title, tags, wikitext));
+        }
+
+        let mut sites = self.sites.lock().expect("Search index lock poisoned");
+        let site = sites.entry(wiki_id).or_insert_with(Site::new);
+        let queued = site.queued.take().unwrap_or_default();
+
+        info!(
+            "Loaded search index for wiki ID {} ({} pages, {} terms, {} queued changes)",
+            wiki_id,
+            index.documents.len(),
+            index.postings.len(),
+            queued.len(),
+        );
+
+        for change in queued {
+            change.apply(&mut index);
+        }
+
+        site.index = Some(index);
+    }
+
+    /// Applies a change to a wiki's index, or queues it if the index is loading.
+    /// If the index hasn't been loaded, this does nothing, as loading will include the change.
+    fn change<F>(&self, wiki_id: WikiId, change: F)
+    where
+        F: FnOnce() -> Change,
+    {
+        let mut sites = self.sites.lock().expect("Search index lock poisoned");
+
+        match sites.get_mut(&wiki_id) {
+            Some(Site {
+                index: Some(index), ..
+            }) => change().apply(index),
+            Some(Site {
+                queued: Some(queued),
+                ..
+            }) => queued.push(change()),
+            _ => (),
+        }
+    }
+
+    /// Adds or replaces a page in a wiki's index.
+    pub fn update(
+        &self,
+        wiki_id: WikiId,
+        slug: &str,
+        title: &str,
+        tags: &[String],
+        wikitext: &str,
+    ) {
+        self.change(wiki_id, || Change::Update {
+            slug: String::from(slug),
+            title: String::from(title),
+            tags: tags.to_vec(),
+            wikitext: String::from(wikitext),
+        });
+    }
+
+    /// Changes the tags of a page in a wiki's index.
+    pub fn update_tags(&self, wiki_id: WikiId, slug: &str, tags: &[String]) {
+        self.change(wiki_id, || Change::Tags {
+            slug: String::from(slug),
+            tags: tags.to_vec(),
+        });
+    }
+
+    /// Moves a page in a wiki's index after it has been renamed.
+    pub fn rename(&self, wiki_id: WikiId, slug: &str, new_slug: &str) {
+        self.change(wiki_id, || Change::Rename {
+            slug: String::from(slug),
+            new_slug: String::from(new_slug),
+        });
+    }
+
+    /// Removes a page from a wiki's index.
+    pub fn remove(&self, wiki_id: WikiId, slug: &str) {
+        self.change(wiki_id, || Change::Remove {
+            slug: String::from(slug),
+        });
+    }
+
+    /// Searches a wiki for pages containing all the words in `query`, best matches first.
+    ///
+    /// Only pages for which `allowed` returns `true` are included. Returns the total
+    /// number of matches, and those from `offset` up to `limit` of them.
+    /// Snippets are only built for the returned matches, after releasing the index.
+    pub fn search<F>(
+        &self,
+        wiki_id: WikiId,
+        query: &str,
+        filter: &SearchFilter,
+        allowed: F,
+        offset: usize,
+        limit: usize,
+    ) -> (usize, Vec<SearchResult>)
+    where
+        F: Fn(&str) -> bool,
+    {
+        let terms: Vec<String> = tokenize(query).collect();
+        if terms.is_empty() {
+            return (0, Vec::new());
+        }
+
+        let (total, matches) = {
+            let sites = self.sites.lock().expect("Search index lock poisoned");
+            let index = match sites.get(&wiki_id).and_then(|site| site.index.as_ref()) {
+                Some(index) => index,
+                None => return (0, Vec::new()),
+            };
+
+            // Find pages with every term, starting from the rarest
+            let mut postings: Vec<&HashSet<String>> = Vec::with_capacity(terms.len());
+            for term in &terms {
+                match index.postings.get(term) {
+                    Some(slugs) => postings.push(slugs),
+                    None => return (0, Vec::new()),
+                }
+            }
+
+            postings.sort_by_key(|slugs| slugs.len());
+
+            let (first, rest) = postings.split_first().expect("No terms in search");
+            let mut matches: Vec<(&String, &Document, usize)> = first
+                .iter()
+                .filter(|slug| rest.iter().all(|slugs| slugs.contains(*slug)))
+                .filter(|slug| allowed(slug))
+                .filter_map(|slug| {
+                    let document = &index.documents[slug.as_str()];
+
+                    if let Some(category) = filter.category {
+                        let page_category = match slug.find(':') {
+                            Some(idx) => &slug[..idx],
+                            None => "_default",
+                        };
+
+                        if !page_category.eq_ignore_ascii_case(category) {
+                            return None;
+                        }
+                    }
+
+                    if let Some(tags) = filter.tags {
+                        if !tags.matches(&document.tags) {
+                            return None;
+                        }
+                    }
+
+                    Some((slug, document, document.score(&terms)))
+                })
+                .collect();
+
+            matches.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+
+            let total = matches.len();
+            let matches: Vec<_> = matches
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .map(|(slug, document, score)| {
+                    (
+                        slug.clone(),
+                        document.title.clone(),
+                        score,
+                        Arc::clone(&document.text),
+                    )
+                })
+                .collect();
+
+            (total, matches)
+        };
+
+        let results = matches
+            .into_iter()
+            .map(|(slug, title, score, text)| SearchResult {
+                slug,
+                title,
+                score,
+                snippet: snippet(&text, &terms),
+            })
+            .collect();
+
+        (total, results)
+    }
+}
+
+impl Debug for SearchIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SearchIndex")
+            .field("sites", &"Mutex<HashMap<WikiId, Site>>")
+            .finish()
+    }
+}
+
+/// Builds an HTML snippet of the text around the first match,
+/// with matching words wrapped in `<mark>`.
+fn snippet(text: &str, terms: &[String]) -> String {
+    // Find the words in the text, with their byte positions
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (idx, ch) in text.char_indices().chain(Some((text.len(), ' '))) {
+        match (start, ch.is_alphanumeric()) {
+            (None, true) => start = Some(idx),
+            (Some(begin), false) => {
+                words.push((begin, idx));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+
+    let is_match = |&(begin, end): &(usize, usize)| {
+        let word = text[begin..end].to_lowercase();
+        terms.contains(&word)
+    };
+
+    let first = match words.iter().find(|word| is_match(word)) {
+        Some(&(begin, _)) => begin,
+        None => 0,
+    };
+
+    // Find the snippet's boundaries, on character boundaries
+    let from = text[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT)
+        .map(|(idx, _)| idx)
+        .unwrap_or(0);
+
+    let to = text[first..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT * 2)
+        .map(|(idx, _)| first + idx)
+        .unwrap_or_else(|| text.len());
+
+    // Build highlighted HTML
+    let mut html = String::new();
+    let mut last = from;
+
+    if from > 0 {
+        html.push_str("&hellip;");
+    }
+
+    for word in words
+        .iter()
+        .filter(|&&(begin, end)| begin >= from && end <= to)
+    {
+        if is_match(word) {
+            let (begin, end) = *word;
+
+            html.push_str(&escape_html(&text[last..begin]));
+            html.push_str("<mark>");
+            html.push_str(&escape_html(&text[begin..end]));
+            html.push_str("</mark>");
+            last = end;
+        }
+    }
+
+    html.push_str(&escape_html(&text[last..to]));
+
+    if to < text.len() {
+        html.push_str("&hellip;");
+    }
+
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn search(index: &SearchIndex, wiki_id: WikiId, query: &str) -> Vec<String> {
+        let (_, results) = index.search(wiki_id, query, &SearchFilter::default(), |_| true, 0, 10);
+
+        results.into_iter().map(|result| result.slug).collect()
+    }
+
+    #[test]
+    fn paginate() {
+        let index = SearchIndex::new();
+        let wiki_id = WikiId::from_raw(1);
+        let pages = vec![
+            ("a", "Apple", &[][..], "Fruit which is red"),
+            ("b", "Banana", &[][..], "Fruit which is yellow"),
+            ("c", "Fruit", &[][..], "Not a fruit"),
+        ];
+
+        index.load(wiki_id, pages);
+
+        let filter = SearchFilter::default();
+        let (total, results) = index.search(wiki_id, "fruit", &filter, |_| true, 1, 1);
+        assert_eq!(total, 3);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].slug, "a");
+        assert_eq!(results[0].snippet, "<mark>Fruit</mark> which is red");
+
+        let (total, results) = index.search(wiki_id, "fruit", &filter, |slug| slug != "c", 0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn queued_changes() {
+        let index = SearchIndex::new();
+        let wiki_id = WikiId::from_raw(1);
+
+        // Changes before loading starts are already in the pages loaded
+        index.update(wiki_id, "a", "Apple", &[], "Green fruit");
+        assert!(!index.is_loaded(wiki_id));
+
+        // Changes during loading may be newer than the pages loaded
+        index.start_load(wiki_id);
+        index.update(wiki_id, "b", "Banana", &[], "Yellow fruit");
+        index.rename(wiki_id, "a", "c");
+        index.load(wiki_id, vec![("a", "Apple", &[][..], "Red fruit")]);
+
+        assert!(index.is_loaded(wiki_id));
+        assert_eq!(search(&index, wiki_id, "fruit"), vec!["b", "c"]);
+        assert_eq!(search(&index, wiki_id, "red"), vec!["c"]);
+        assert!(search(&index, wiki_id, "green").is_empty());
+
+        // Once loaded, changes apply directly
+        index.remove(wiki_id, "b");
+        assert_eq!(search(&index, wiki_id, "fruit"), vec!["c"]);
+    }
+}
@@ -18,10 +18,20 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use crate::attachment::Attachments;
+use crate::cache::RenderCache;
 use crate::config::RuntimeSettings;
+use crate::feed::FeedCache;
+use crate::hooks::PageHooks;
+use crate::lock::EditLocks;
 use crate::middleware as crate_middleware;
+use crate::page_list::PageListCache;
+use crate::rating::ScoreCache;
 use crate::remote::{DeepwellPool, FtmlPool};
 use crate::route::*;
+use crate::search::SearchIndex;
+use crate::sitemap::SitemapCache;
+use crate::template::Templates;
 use crate::utils::get_client_ip;
 use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_ratelimit::{MemoryStore, MemoryStoreActor, RateLimiter};
@@ -40,12 +50,24 @@ pub struct Server {
     pub keep_alive: usize,
     pub ratelimit_requests: usize,
     pub ratelimit_interval: Duration,
+    pub preview_ratelimit_requests: usize,
+    pub preview_ratelimit_interval: Duration,
     pub cookie_secure: bool,
     pub cookie_max_age: i64,
     pub cookie_same_site: SameSite,
     pub cookie_key: Box<[u8]>,
     pub deepwell: DeepwellPool,
     pub ftml: FtmlPool,
+    pub templates: Templates,
+    pub render_cache: RenderCache,
+    pub page_lists: PageListCache,
+    pub scores: ScoreCache,
+    pub edit_locks: EditLocks,
+    pub attachments: Attachments,
+    pub search_index: SearchIndex,
+    pub sitemaps: SitemapCache,
+    pub feeds: FeedCache,
+    pub page_hooks: PageHooks,
 }
 
 impl Server {
@@ -57,15 +79,35 @@ impl Server {
             keep_alive,
             ratelimit_requests,
             ratelimit_interval,
+            preview_ratelimit_requests,
+            preview_ratelimit_interval,
             cookie_secure,
             cookie_max_age,
             cookie_same_site,
             cookie_key,
             deepwell,
             ftml,
+            templates,
+            render_cache,
+            page_lists,
+            scores,
+            edit_locks,
+            attachments,
+            search_index,
+            sitemaps,
+            feeds,
+            page_hooks,
         } = self;
 
+        let page_services = PageServices {
+            ftml: ftml.clone(),
+            templates,
+            cache: render_cache.clone(),
+            page_lists: page_lists.clone(),
+        };
+
         let ratelimit_store = MemoryStore::new();
+        let preview_ratelimit_store = MemoryStore::new();
 
         HttpServer::new(move || {
             App::new()
@@ -73,6 +115,16 @@ impl Server {
                 .data(Client::default())
                 .data(deepwell.clone())
                 .data(ftml.clone())
+                .data(render_cache.clone())
+                .data(page_lists.clone())
+                .data(scores.clone())
+                .data(edit_locks.clone())
+                .data(attachments.clone())
+                .data(search_index.clone())
+                .data(sitemaps.clone())
+                .data(feeds.clone())
+                .data(page_hooks.clone())
+                .data(page_services.clone())
                 .data(settings.clone())
                 // Middleware
                 .wrap(actix_middleware::Compress::default())
@@ -91,6 +143,15 @@ impl Server {
                 ))
                 .wrap(crate_middleware::WikidotNormalizePath::default())
                 .wrap(actix_middleware::Logger::default())
+                // Files attached to pages
+                .service(web::resource("local--files/{page}/{filename}").to(local_file))
+                // Feeds
+                .service(web::resource("feed/pages.{ext}").to(feed_pages))
+                .service(web::resource("feed/site-changes.{ext}").to(feed_site_changes))
+                // TODO forum category and thread feeds, once the forum is implemented
+                // Sitemaps
+                .service(web::resource("sitemap.xml").to(sitemap_main))
+                .service(web::resource("sitemap-{number}.xml").to(sitemap_part))
                 // Static files (e.g. favicon, robots.txt)
                 .service(web::resource("{filename}.{ext}").to(static_file))
                 // Forum redirects
@@ -132,6 +193,9 @@ impl Server {
                                 .route("version", web::get().to(api_version))
                                 .route("build", web::get().to(api_build))
                                 .route("debug", web::to(api_debug))
+                                .route("tags", web::get().to(api_tags_all))
+                                .route("recent-changes", web::get().to(api_recent_changes))
+                                .route("search", web::get().to(api_search))
                                 .service(
                                     web::scope("auth")
                                         .route("", web::get().to(api_route))
@@ -146,22 +210,61 @@ impl Server {
                                 .service(
                                     web::scope("page")
                                         .route("", web::get().to(api_route))
-                                        .route("edit-lock", web::post().to(temp_api))
-                                        .route("history", web::get().to(temp_api))
-                                        .route("parent", web::get().to(temp_api))
-                                        .route("parent", web::post().to(temp_api))
-                                        .route("rename", web::post().to(temp_api))
-                                        .route("revision", web::get().to(temp_api))
-                                        .route("source", web::get().to(temp_api))
-                                        .route("tags", web::get().to(temp_api))
-                                        .route("tags", web::post().to(temp_api))
-                                        .route("vote", web::get().to(temp_api))
-                                        .route("vote", web::post().to(temp_api))
-                                        .route("vote", web::delete().to(temp_api)),
+                                        .route("create", web::post().to(api_page_create))
+                                        .route("delete", web::post().to(api_page_delete))
+                                        .route("deleted", web::get().to(api_page_deleted))
+                                        .route("diff", web::get().to(api_page_diff))
+                                        .route("edit", web::post().to(api_page_edit))
+                                        .route("edit-lock", web::get().to(api_edit_lock_status))
+                                        .route("edit-lock", web::post().to(api_edit_lock_acquire))
+                                        .route("edit-lock", web::delete().to(api_edit_lock_release))
+                                        .route("files", web::get().to(api_page_files_list))
+                                        .route("files", web::post().to(api_page_files_upload))
+                                        .route("files", web::delete().to(api_page_files_delete))
+                                        .route(
+                                            "files/rename",
+                                            web::post().to(api_page_files_rename),
+                                        )
+                                        .route("history", web::get().to(api_page_history))
+                                        .route("parent", web::get().to(api_page_parent_get))
+                                        .route("parent", web::post().to(api_page_parent_set))
+                                        .service(
+                                            web::resource("preview")
+                                                .wrap(
+                                                    RateLimiter::new(
+                                                        MemoryStoreActor::from(
+                                                            preview_ratelimit_store.clone(),
+                                                        )
+                                                        .start(),
+                                                    )
+                                                    .with_max_requests(preview_ratelimit_requests)
+                                                    .with_interval(preview_ratelimit_interval)
+                                                    .with_identifier(|req| {
+                                                        Ok(get_client_ip(&req).to_string())
+                                                    }),
+                                                )
+                                                .route(web::post().to(api_page_preview)),
+                                        )
+                                        .route("purge", web::post().to(api_page_purge))
+                                        .route("query", web::get().to(api_page_query))
+                                        .route("rename", web::post().to(api_page_rename))
+                                        .route("restore", web::post().to(api_page_restore))
+                                        .route("revision", web::get().to(api_page_revision))
+                                        .route("source", web::get().to(api_page_source))
+                                        .route("source/raw", web::get().to(api_page_source_raw))
+                                        .route("tags", web::get().to(api_page_tags_get))
+                                        .route("tags", web::post().to(api_page_tags_edit))
+                                        .route("vote", web::get().to(api_page_vote_status))
+                                        .route("vote", web::post().to(api_page_vote_cast))
+                                        .route("vote", web::delete().to(api_page_vote_remove)),
                                 )
                                 .service(web::scope("user").route("info", web::get().to(temp_api))),
                         ),
                 )
+                // Search
+                .service(web::resource("search").to(search_page))
+                // Printer-friendly pages
+                .service(web::resource("printer--friendly/{page:.*}").to(page_printer_friendly))
                 // Pages
                 .service(web::resource("{name}").to(page_get))
                 .service(web::resource("{name}/{options:.*}").to(page_get))
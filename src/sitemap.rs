@@ -0,0 +1,223 @@
+/*
+ * sitemap.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Generation and caching of `sitemap.xml` files for crawlers.
+//!
+//! Sitemaps may list at most 50,000 URLs, so larger sites are split into
+//! several sitemaps, with `/sitemap.xml` serving an index of them instead.
+//! Generated sitemaps are cached per wiki until one of its pages changes.
+
+use crate::utils::escape_html;
+use chrono::{DateTime, SecondsFormat, Utc};
+use deepwell_core::types::WikiId;
+use futures::lock::Mutex as AsyncMutex;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+
+/// The most URLs allowed in one sitemap file.
+pub const MAX_SITEMAP_URLS: usize = 50_000;
+
+const SITEMAP_NAMESPACE: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+
+/// A page to list in a sitemap.
+#[derive(Debug, Clone)]
+pub struct SitemapEntry {
+    pub slug: String,
+    pub updated: DateTime<Utc>,
+}
+
+/// The generated sitemap files for a site.
+#[derive(Debug, Clone)]
+pub struct Sitemap {
+    main: String,
+    parts: Vec<String>,
+}
+
+impl Sitemap {
+    /// Builds a sitemap from a site's pages.
+    /// `base` is the site's URL, such as `https://example.com`.
+    pub fn build(base: &str, entries: &[SitemapEntry]) -> Self {
+        if entries.len() <= MAX_SITEMAP_URLS {
+            return Sitemap {
+                main: url_set(base, entries),
+                parts: Vec::new(),
+            };
+        }
+
+        let parts: Vec<String> = entries
+            .chunks(MAX_SITEMAP_URLS)
+            .map(|chunk| url_set(base, chunk))
+            .collect();
+
+        let mut main = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"{}\">\n",
+            SITEMAP_NAMESPACE,
+        );
+
+        for (index, chunk) in entries.chunks(MAX_SITEMAP_URLS).enumerate() {
+            let updated = chunk.iter().map(|entry| entry.updated).max();
+
+            main.push_str(&format!(
+                "<sitemap>\n<loc>{}/sitemap-{}.xml</loc>\n",
+                escape_html(base),
+                index + 1,
+            ));
+
+            if let Some(updated) = updated {
+                main.push_str(&format!("<lastmod>{}</lastmod>\n", w3c_date(updated)));
+            }
+
+            main.push_str("</sitemap>\n");
+        }
+
+        main.push_str("</sitemapindex>\n");
+
+        Sitemap { main, parts }
+    }
+
+    /// Gets `/sitemap.xml`, which is either the only sitemap, or an index of the others.
+    #[inline]
+    pub fn main(&self) -> &str {
+        &self.main
+    }
+
+    /// Gets `/sitemap-{number}.xml`, numbered from 1, if the sitemap is split up.
+    pub fn part(&self, number: usize) -> Option<&str> {
+        if number == 0 {
+            return None;
+        }
+
+        self.parts.get(number - 1).map(|part| part.as_str())
+    }
+}
+
+fn url_set(base: &str, entries: &[SitemapEntry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"{}\">\n",
+        SITEMAP_NAMESPACE,
+    );
+
+    for entry in entries {
+        xml.push_str(&format!(
+            "<url>\n<loc>{}/{}</loc>\n<lastmod>{}</lastmod>\n</url>\n",
+            escape_html(base),
+            escape_html(&entry.slug),
+            w3c_date(entry.updated),
+        ));
+    }
+
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[inline]
+fn w3c_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+struct Entry {
+    sitemap: Option<Arc<Sitemap>>,
+    generation: u64,
+    loading: Arc<AsyncMutex<()>>,
+}
+
+impl Entry {
+    fn new() -> Self {
+        Entry {
+            sitemap: None,
+            generation: 0,
+            loading: Arc::new(AsyncMutex::new(())),
+        }
+    }
+}
+
+/// Cache of generated sitemaps, by wiki.
+#[derive(Clone, Default)]
+pub struct SitemapCache {
+    wikis: Arc<Mutex<HashMap<WikiId, Entry>>>,
+}
+
+impl SitemapCache {
+    #[inline]
+    pub fn new() -> Self {
+        SitemapCache::default()
+    }
+
+    /// Gets a wiki's cached sitemap.
+    /// If it isn't cached, returns the current generation, to pass to `insert` after building it.
+    pub fn get(&self, wiki_id: WikiId) -> Result<Arc<Sitemap>, u64> {
+        let wikis = self.wikis.lock().expect("Sitemap cache lock poisoned");
+
+        match wikis.get(&wiki_id) {
+            Some(Entry {
+                sitemap: Some(sitemap),
+                ..
+            }) => Ok(Arc::clone(sitemap)),
+            Some(entry) => Err(entry.generation),
+            None => Err(0),
+        }
+    }
+
+    /// Gets the lock held while building a wiki's sitemap,
+    /// so that concurrent requests wait for one build instead of each starting their own.
+    pub fn load_lock(&self, wiki_id: WikiId) -> Arc<AsyncMutex<()>> {
+        let mut wikis = self.wikis.lock().expect("Sitemap cache lock poisoned");
+        let entry = wikis.entry(wiki_id).or_insert_with(Entry::new);
+
+        Arc::clone(&entry.loading)
+    }
+
+    /// Caches a wiki's sitemap, built when the cache was at `generation`.
+    ///
+    /// If a page changed while it was being built, it is returned without being cached.
+    pub fn insert(&self, wiki_id: WikiId, generation: u64, sitemap: Sitemap) -> Arc<Sitemap> {
+        let sitemap = Arc::new(sitemap);
+        let mut wikis = self.wikis.lock().expect("Sitemap cache lock poisoned");
+        let entry = wikis.entry(wiki_id).or_insert_with(Entry::new);
+
+        if entry.generation == generation {
+            entry.sitemap = Some(Arc::clone(&sitemap));
+        } else {
+            debug!("Sitemap for wiki ID {} changed while building", wiki_id);
+        }
+
+        sitemap
+    }
+
+    /// Removes the cached sitemap for a wiki, so it is regenerated on the next request.
+    pub fn invalidate(&self, wiki_id: WikiId) {
+        debug!("Invalidating cached sitemap for wiki ID {}", wiki_id);
+
+        let mut wikis = self.wikis.lock().expect("Sitemap cache lock poisoned");
+        let entry = wikis.entry(wiki_id).or_insert_with(Entry::new);
+
+        entry.sitemap = None;
+        entry.generation += 1;
+    }
+}
+
+impl Debug for SitemapCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SitemapCache")
+            .field("wikis", &"Mutex<HashMap<WikiId, Entry>>")
+            .finish()
+    }
+}
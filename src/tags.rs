@@ -0,0 +1,190 @@
+/*
+ * tags.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers for page tags, following Wikidot's tag rules.
+//!
+//! Tags are case-insensitive and stored in lowercase. They cannot contain
+//! whitespace, and tags beginning with `_` are hidden from tag listings.
+//! They also can't begin with `+` or `-`, which mark required and excluded tags in queries.
+
+use deepwell_core::types::Page;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// Longest allowed tag, in bytes.
+pub const MAX_TAG_LENGTH: usize = 64;
+
+lazy_static! {
+    static ref TAG_REGEX: Regex = Regex::new(r"^[a-z0-9_:.#@&][a-z0-9_\-:+.#@&]*$").unwrap();
+}
+
+/// Converts a tag to its normal form.
+#[inline]
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Determines if a (normalized) tag is allowed.
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.len() <= MAX_TAG_LENGTH && TAG_REGEX.is_match(tag)
+}
+
+/// Determines if a tag should be hidden from tag listings.
+#[inline]
+pub fn is_hidden_tag(tag: &str) -> bool {
+    tag.starts_with('_')
+}
+
+/// Counts how many pages have each tag, sorted by tag.
+/// Hidden tags are not included.
+pub fn count_tags<'a, I>(pages: I) -> BTreeMap<&'a str, usize>
+where
+    I: IntoIterator<Item = &'a Page>,
+{
+    let mut counts = BTreeMap::new();
+
+    for page in pages {
+        for tag in page.tags().iter().filter(|tag| !is_hidden_tag(tag)) {
+            *counts.entry(tag.as_str()).or_insert(0) += 1;
+        }
+    }
+
+    counts
+}
+
+/// A tag filter in Wikidot's ListPages syntax, for instance `+scp -joke keter euclid`.
+///
+/// Tags prefixed with `+` are required, those with `-` are excluded,
+/// and if there are any others, the page must have at least one of them.
+///
+/// In a URL query string a `+` decodes to a space, so it must be written as `%2B`.
+/// Query parameters can instead list required tags separately, see `from_params`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagQuery {
+    pub required: Vec<String>,
+    pub excluded: Vec<String>,
+    pub any: Vec<String>,
+}
+
+impl TagQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut tag_query = TagQuery::default();
+
+        for part in query.split_whitespace() {
+            let (list, tag) = match part.chars().next() {
+                Some('+') => (&mut tag_query.required, &part[1..]),
+                Some('-') => (&mut tag_query.excluded, &part[1..]),
+                _ => (&mut tag_query.any, part),
+            };
+
+            let tag = normalize_tag(tag);
+            if !tag.is_empty() {
+                list.push(tag);
+            }
+        }
+
+        tag_query
+    }
+
+    /// Builds a tag filter from query parameters: `tags` in `ListPages` syntax,
+    /// and `required`, a list of tags which pages must have, without any `+`.
+    /// Returns `None` if neither was given.
+    pub fn from_params(tags: Option<&str>, required: Option<&str>) -> Option<Self> {
+        if tags.is_none() && required.is_none() {
+            return None;
+        }
+
+        let mut tag_query = TagQuery::parse(tags.unwrap_or(""));
+
+        for tag in required.unwrap_or("").split_whitespace() {
+            tag_query.required.push(normalize_tag(tag));
+        }
+
+        Some(tag_query)
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        let has = |tag: &String| tags.contains(tag);
+
+        self.required.iter().all(has)
+            && !self.excluded.iter().any(has)
+            && (self.any.is_empty() || self.any.iter().any(has))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| String::from(*tag)).collect()
+    }
+
+    #[test]
+    fn valid_tags() {
+        assert!(is_valid_tag("scp"));
+        assert!(is_valid_tag("_cc"));
+        assert!(is_valid_tag("c++"));
+        assert!(is_valid_tag("#hashtag"));
+        assert!(is_valid_tag("keter-class"));
+        assert!(!is_valid_tag(""));
+        assert!(!is_valid_tag("+scp"));
+        assert!(!is_valid_tag("-joke"));
+        assert!(!is_valid_tag("Upper"));
+        assert!(!is_valid_tag("two words"));
+        assert!(!is_valid_tag(&"a".repeat(MAX_TAG_LENGTH + 1)));
+    }
+
+    #[test]
+    fn parse_query() {
+        let query = TagQuery::parse("+SCP -joke  keter euclid +c++");
+
+        assert_eq!(query.required, tags(&["scp", "c++"]));
+        assert_eq!(query.excluded, tags(&["joke"]));
+        assert_eq!(query.any, tags(&["keter", "euclid"]));
+
+        // Lone operators are ignored
+        assert_eq!(TagQuery::parse("+ - "), TagQuery::default());
+    }
+
+    #[test]
+    fn query_params() {
+        assert_eq!(TagQuery::from_params(None, None), None);
+
+        // As decoded from "?tags=+scp -joke", where the '+' became a space
+        let query = TagQuery::from_params(Some(" scp -joke"), Some("tale c++")).unwrap();
+
+        assert_eq!(query.required, tags(&["tale", "c++"]));
+        assert_eq!(query.excluded, tags(&["joke"]));
+        assert_eq!(query.any, tags(&["scp"]));
+    }
+
+    #[test]
+    fn matches() {
+        let query = TagQuery::parse("+scp -joke keter euclid");
+
+        assert!(query.matches(&tags(&["scp", "keter"])));
+        assert!(query.matches(&tags(&["scp", "euclid", "humanoid"])));
+        assert!(!query.matches(&tags(&["scp", "safe"])));
+        assert!(!query.matches(&tags(&["scp", "keter", "joke"])));
+        assert!(!query.matches(&tags(&["tale", "keter"])));
+        assert!(TagQuery::default().matches(&[]));
+    }
+}
@@ -0,0 +1,365 @@
+/*
+ * template.rs
+ *
+ * thaumiel - Wikidot-like web server to provide pages, forums, and other services
+ * Copyright (C) 2019-2020 Ammon Smith
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Site layout templates, which wrap rendered page contents.
+//!
+//! Templates are read from the configured template directory at startup:
+//! * `layout.html` is the overall document, with `{{slot}}` placeholders.
+//! * `header.html` and `footer.html` are inserted into their respective slots.
+//! * `print.html` is the document for printer-friendly pages, without navigation.
+//!   If absent, a minimal built-in layout is used.
+//!
+//! Any of these can be overridden for a particular site by placing a file of the
+//! same name in a subdirectory named after the wiki's slug, for instance
+//! `templates/scp-wiki/layout.html`. Files not present there use the default.
+
+use crate::utils::escape_html;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The printer-friendly layout used if there is no `print.html` template.
+const DEFAULT_PRINT_LAYOUT: &str = include_str!("../misc/templates/print.html");
+
+const SLOTS: [(&str, Slot); 10] = [
+    ("title", Slot::Title),
+    ("head", Slot::Head),
+    ("style", Slot::Style),
+    ("header", Slot::Header),
+    ("nav-top", Slot::NavTop),
+    ("nav-side", Slot::NavSide),
+    ("page-title", Slot::PageTitle),
+    ("rating", Slot::Rating),
+    ("content", Slot::Content),
+    ("footer", Slot::Footer),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    Title,
+    Head,
+    Style,
+    Header,
+    NavTop,
+    NavSide,
+    PageTitle,
+    Rating,
+    Content,
+    Footer,
+}
+
+impl Slot {
+    fn from_name(name: &str) -> Option<Self> {
+        for (text, slot) in &SLOTS {
+            if name.eq_ignore_ascii_case(text) {
+                return Some(*slot);
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Slot(Slot),
+}
+
+/// A parsed layout template.
+#[derive(Debug, Clone)]
+struct Layout {
+    parts: Vec<Part>,
+}
+
+impl Layout {
+    fn parse(source: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut remaining = source;
+
+        while let Some(start) = remaining.find("{{") {
+            let end = match remaining[start..].find("}}") {
+                Some(offset) => start + offset,
+                None => break,
+            };
+
+            let name = remaining[start + 2..end].trim();
+
+            match Slot::from_name(name) {
+                Some(slot) => {
+                    parts.push(Part::Text(String::from(&remaining[..start])));
+                    parts.push(Part::Slot(slot));
+                }
+                None => {
+                    warn!("Unknown slot '{}' in layout template", name);
+
+                    parts.push(Part::Text(String::from(&remaining[..end + 2])));
+                }
+            }
+
+            remaining = &remaining[end + 2..];
+        }
+
+        parts.push(Part::Text(String::from(remaining)));
+
+        Layout { parts }
+    }
+}
+
+/// The set of templates used by one site.
+#[derive(Debug, Clone)]
+struct SiteTemplates {
+    layout: Layout,
+    print: Layout,
+    header: String,
+    footer: String,
+}
+
+impl SiteTemplates {
+    fn render(&self, layout: &Layout, context: &LayoutContext) -> String {
+        let title = escape_html(context.title);
+        let mut output = String::new();
+
+        for part in &layout.parts {
+            let text: &str = match part {
+                Part::Text(text) => text,
+                Part::Slot(Slot::Title) => &title,
+                Part::Slot(Slot::Head) => context.head,
+                Part::Slot(Slot::Style) => context.style,
+                Part::Slot(Slot::Header) => &self.header,
+                Part::Slot(Slot::NavTop) => context.nav_top,
+                Part::Slot(Slot::NavSide) => context.nav_side,
+                Part::Slot(Slot::PageTitle) => &title,
+                Part::Slot(Slot::Rating) => context.rating,
+                Part::Slot(Slot::Content) => context.content,
+                Part::Slot(Slot::Footer) => &self.footer,
+            };
+
+            output.push_str(text);
+        }
+
+        output
+    }
+}
+
+/// The values to fill a layout template's slots with.
+#[derive(Debug, Copy, Clone)]
+pub struct LayoutContext<'a> {
+    /// The page title, unescaped.
+    pub title: &'a str,
+    pub head: &'a str,
+    pub style: &'a str,
+    pub nav_top: &'a str,
+    pub nav_side: &'a str,
+    pub rating: &'a str,
+    pub content: &'a str,
+}
+
+/// All loaded site templates, cheaply cloneable.
+#[derive(Debug, Clone)]
+pub struct Templates {
+    default: Arc<SiteTemplates>,
+    sites: Arc<HashMap<String, SiteTemplates>>,
+}
+
+impl Templates {
+    #[cold]
+    pub fn load(directory: &Path) -> Self {
+        info!("Loading templates from {}", directory.display());
+
+        let layout = read_template(directory, "layout.html")
+            .expect("Unable to read default layout template")
+            .expect("No default layout template (layout.html) in template directory");
+
+        let read_fragment = |name| {
+            read_template(directory, name)
+                .expect("Unable to read default template")
+                .unwrap_or_default()
+        };
+
+        let print = read_template(directory, "print.html")
+            .expect("Unable to read default print template")
+            .unwrap_or_else(|| String::from(DEFAULT_PRINT_LAYOUT));
+
+        let default = SiteTemplates {
+            layout: Layout::parse(&layout),
+            print: Layout::parse(&print),
+            header: read_fragment("header.html"),
+            footer: read_fragment("footer.html"),
+        };
+
+        // Load per-site overrides
+        let mut sites = HashMap::new();
+        let entries = fs::read_dir(directory).expect("Unable to read template directory");
+
+        for entry in entries {
+            let entry = entry.expect("Unable to read template directory entry");
+            let path = entry.path();
+
+            if !path.is_dir() {
+                continue;
+            }
+
+            let site = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => String::from(name),
+                None => continue,
+            };
+
+            debug!("Loading templates for site '{}'", site);
+
+            let read_override =
+                |name| read_template(&path, name).expect("Unable to read site template override");
+
+            let templates = SiteTemplates {
+                layout: match read_override("layout.html") {
+                    Some(layout) => Layout::parse(&layout),
+                    None => default.layout.clone(),
+                },
+                print: match read_override("print.html") {
+                    Some(print) => Layout::parse(&print),
+                    None => default.print.clone(),
+                },
+                header: read_override("header.html").unwrap_or_else(|| default.header.clone()),
+                footer: read_override("footer.html").unwrap_or_else(|| default.footer.clone()),
+            };
+
+            sites.insert(site, templates);
+        }
+
+        Templates {
+            default: Arc::new(default),
+            sites: Arc::new(sites),
+        }
+    }
+
+    /// Renders a full HTML document using the given site's layout.
+    pub fn render(&self, wiki_slug: Option<&str>, context: &LayoutContext) -> String {
+        let templates = self.site(wiki_slug);
+
+        templates.render(&templates.layout, context)
+    }
+
+    /// Renders a printer-friendly HTML document using the given site's print layout.
+    pub fn render_print(&self, wiki_slug: Option<&str>, context: &LayoutContext) -> String {
+        let templates = self.site(wiki_slug);
+
+        templates.render(&templates.print, context)
+    }
+
+    fn site(&self, wiki_slug: Option<&str>) -> &SiteTemplates {
+        wiki_slug
+            .and_then(|slug| self.sites.get(slug))
+            .unwrap_or(&*self.default)
+    }
+}
+
+fn read_template(directory: &Path, name: &str) -> io::Result<Option<String>> {
+    let path = directory.join(name);
+
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(text: &str) -> Part {
+        Part::Text(String::from(text))
+    }
+
+    #[test]
+    fn parse_slots() {
+        let layout = Layout::parse("<title>{{title}}</title>{{ Content }}!");
+
+        assert_eq!(
+            layout.parts,
+            vec![
+                text("<title>"),
+                Part::Slot(Slot::Title),
+                text("</title>"),
+                Part::Slot(Slot::Content),
+                text("!"),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_plain() {
+        assert_eq!(Layout::parse("").parts, vec![text("")]);
+        assert_eq!(Layout::parse("<p>x</p>").parts, vec![text("<p>x</p>")]);
+    }
+
+    #[test]
+    fn parse_unknown_slot() {
+        let layout = Layout::parse("a{{nonsense}}b{{footer}}");
+
+        assert_eq!(
+            layout.parts,
+            vec![
+                text("a{{nonsense}}"),
+                text("b"),
+                Part::Slot(Slot::Footer),
+                text(""),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_unterminated() {
+        let layout = Layout::parse("{{nav-top}} {{content");
+
+        assert_eq!(
+            layout.parts,
+            vec![text(""), Part::Slot(Slot::NavTop), text(" {{content")],
+        );
+    }
+
+    #[test]
+    fn render_layout() {
+        let layout = Layout::parse("<h1>{{page-title}}</h1>{{header}}{{content}}{{rating}}");
+        let templates = SiteTemplates {
+            layout: layout.clone(),
+            print: layout,
+            header: String::from("<header />"),
+            footer: String::new(),
+        };
+
+        let context = LayoutContext {
+            title: "A & B",
+            head: "",
+            style: "",
+            nav_top: "",
+            nav_side: "",
+            rating: "+5",
+            content: "<p>Text</p>",
+        };
+
+        assert_eq!(
+            templates.render(&templates.layout, &context),
+            "<h1>A &amp; B</h1><header /><p>Text</p>+5",
+        );
+    }
+}
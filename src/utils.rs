@@ -20,8 +20,25 @@
 
 use actix_web::dev::ServiceRequest;
 use actix_web::{http, HttpRequest};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
 use std::net::{IpAddr, Ipv6Addr};
 
+/// Characters which are percent-encoded in a URL path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b':');
+
+lazy_static! {
+    static ref MARKUP_REGEX: Regex =
+        Regex::new(r"\[\[[^\]]*\]\]|\*\*|//|__|--|\^\^|,,|\{\{|\}\}|@@").unwrap();
+    static ref HTML_REGEX: Regex =
+        Regex::new(r"(?is)<(script|style)\b.*?</(script|style)\s*>|<!--.*?-->|<[^>]*>").unwrap();
+}
+
 /// Gets the requested hostname, from URI, then headers if present.
 pub fn get_host(req: &HttpRequest) -> Option<&str> {
     if let Some(host) = req.uri().host() {
@@ -55,3 +72,111 @@ pub fn get_client_ip(req: &ServiceRequest) -> IpAddr {
     // Return [::] if all else fails
     IpAddr::V6(Ipv6Addr::UNSPECIFIED)
 }
+
+/// Gets the slug of the wiki being requested, which is the first component of the hostname.
+/// For instance, `scp-wiki.example.com:8000` is for wiki `scp-wiki`.
+pub fn get_wiki_slug(host: Option<&str>) -> Option<&str> {
+    let host = host?;
+    let host = host.split(':').next()?;
+
+    match host.split('.').next() {
+        Some("") | None => None,
+        Some(slug) => Some(slug),
+    }
+}
+
+/// Escapes text so it can be placed inside HTML elements or attributes.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+/// Removes the most common Wikidot markup, to leave mostly plain text.
+pub fn plain_text(wikitext: &str) -> String {
+    let text = MARKUP_REGEX.replace_all(wikitext, " ");
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Gets the text shown by rendered HTML, leaving out scripts, styles and comments.
+pub fn html_text(html: &str) -> String {
+    let text = HTML_REGEX.replace_all(html, " ");
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Percent-encodes text so it can be used as one segment of a URL path, such as an argument value.
+/// The result is also safe to place inside HTML attributes.
+pub fn encode_path_segment(text: &str) -> String {
+    utf8_percent_encode(text, PATH_SEGMENT).to_string()
+}
+
+/// Decodes a percent-encoded URL path segment.
+pub fn decode_path_segment(text: &str) -> String {
+    percent_decode_str(text).decode_utf8_lossy().into_owned()
+}
+
+/// Converts a slug to Wikidot normal form, for instance `SCP 173` to `scp-173`.
+pub fn normalize_slug(slug: &str) -> String {
+    let path = format!("/{}", slug.trim_matches('/'));
+
+    match wikidot_path::redirect(&path) {
+        Some(normal) => String::from(normal.trim_start_matches('/')),
+        None => String::from(&path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_path() {
+        assert_eq!(encode_path_segment("scp-173"), "scp-173");
+        assert_eq!(encode_path_segment("fragment:a_b.c~"), "fragment:a_b.c~");
+        assert_eq!(encode_path_segment("c++"), "c%2B%2B");
+        assert_eq!(encode_path_segment("#tag&x"), "%23tag%26x");
+        assert_eq!(encode_path_segment("a/b c"), "a%2Fb%20c");
+        assert_eq!(encode_path_segment("\"<é>"), "%22%3C%C3%A9%3E");
+    }
+
+    #[test]
+    fn html_to_text() {
+        assert_eq!(
+            html_text("<p>Item #: <strong>SCP-173</strong></p>"),
+            "Item #: SCP-173"
+        );
+        assert_eq!(html_text("<p>a &lt;b&gt; &amp;&nbsp;c</p>"), "a <b> & c");
+        assert_eq!(
+            html_text("<style>p { color: red; }</style><!-- note --><div>\n  text\n</div>"),
+            "text",
+        );
+    }
+
+    #[test]
+    fn decode_path() {
+        assert_eq!(decode_path_segment("scp-173"), "scp-173");
+        assert_eq!(decode_path_segment("c%2B%2B"), "c++");
+        assert_eq!(decode_path_segment("%23tag%26x"), "#tag&x");
+        assert_eq!(decode_path_segment("%C3%A9"), "é");
+    }
+}